@@ -1,10 +1,8 @@
-use std::{fs::File, sync::Arc};
+use std::sync::Arc;
 
-use rodio::OutputStream;
 use vulkano::{command_buffer::{allocator::CommandBufferAllocator, AutoCommandBufferBuilder}, device::Device, memory::allocator::MemoryAllocator, pipeline::graphics::viewport::Viewport, render_pass::RenderPass};
-use winit::dpi::PhysicalSize;
 
-use crate::renderer::{app::App, misc::SinkExtrapolator};
+use crate::renderer::app::{App, FrameContext};
 
 mod data {
     use std::sync::Arc;
@@ -12,16 +10,13 @@ mod data {
     use anyhow::Result;
     use vulkano::{
         device::Device,
-        image::sampler::Filter,
         memory::allocator::MemoryAllocator,
-        pipeline::{graphics::viewport::Viewport, GraphicsPipeline},
+        pipeline::GraphicsPipeline,
         render_pass::RenderPass,
     };
 
     use crate::{create_graphics_pipeline, renderer::{ext::CommandBufferExt, mesh}};
 
-    use super::*;
-
     pub struct Pipelines {
         pub mesh_pipeline: Arc<GraphicsPipeline>,
     }
@@ -29,7 +24,6 @@ mod data {
         pub fn new(
             device: Arc<Device>,
             render_pass: Arc<RenderPass>,
-            viewport: Viewport,
         ) -> Result<Self> {
             Ok(Self {
                 mesh_pipeline: create_graphics_pipeline(
@@ -37,7 +31,6 @@ mod data {
                     mesh::shaders::vertex::load(device.clone())?,
                     mesh::shaders::fragment::load(device)?,
                     render_pass,
-                    viewport,
                 )?,
             })
         }
@@ -46,9 +39,9 @@ mod data {
     pub struct Meshes {}
     impl Meshes {
         pub fn new(
-            loader_commands: &mut impl CommandBufferExt,
-            allocator: Arc<dyn MemoryAllocator>,
-            device: Arc<Device>,
+            _loader_commands: &mut impl CommandBufferExt,
+            _allocator: Arc<dyn MemoryAllocator>,
+            _device: Arc<Device>,
         ) -> Result<Self> {
             Ok(Self {
                 // duct: Mesh::load_gltf(
@@ -63,38 +56,36 @@ mod data {
     }
 }
 
-pub struct BULLETINMYBRAIN {
-    audio: SinkExtrapolator,
-}
+#[allow(clippy::upper_case_acronyms)]
+pub struct BULLETINMYBRAIN;
 impl App for BULLETINMYBRAIN {
     fn new<L, A: CommandBufferAllocator + 'static>(
-        loader_command_buffer: &mut AutoCommandBufferBuilder<L, A>,
-        allocator: Arc<dyn MemoryAllocator>,
-        device: Arc<Device>,
-        render_pass: Arc<RenderPass>,
-        viewport: Viewport,
+        _loader_command_buffer: &mut AutoCommandBufferBuilder<L, A>,
+        _allocator: Arc<dyn MemoryAllocator>,
+        _device: Arc<Device>,
+        _render_pass: Arc<RenderPass>,
+        _viewport: Viewport,
     ) -> anyhow::Result<Self> {
-        let (_os, osh) = OutputStream::try_default()?;
-        Ok(Self {
-            audio: SinkExtrapolator::new(osh.play_once(File::open("bullet in my brain.ogg")?)?),
-        })
+        Ok(Self)
     }
 
     fn update<L, A: CommandBufferAllocator + 'static>(
         &mut self,
-        upload_command_buffer: &mut AutoCommandBufferBuilder<L, A>,
+        _context: &mut FrameContext,
+        _upload_command_buffer: &mut AutoCommandBufferBuilder<L, A>,
     ) -> anyhow::Result<()> {
         Ok(())
     }
 
     fn draw<L, A: CommandBufferAllocator + 'static>(
         &mut self,
-        render_command_buffer: &mut AutoCommandBufferBuilder<L, A>,
+        _context: &mut FrameContext,
+        _render_command_buffer: &mut AutoCommandBufferBuilder<L, A>,
     ) -> anyhow::Result<()> {
         Ok(())
     }
 
-    fn resize(&mut self, new_size: PhysicalSize<u32>) -> anyhow::Result<()> {
-        Ok(())
+    fn audio(&self) -> Option<&'static str> {
+        Some("bullet in my brain.ogg")
     }
 }
@@ -1,3 +1,20 @@
+use crate::renderer::app::AppEntry;
+
 pub mod free99;
 pub mod ta1lsd005;
 pub mod ta1lsd003;
+
+/// Every app that can be picked from the command line.
+pub fn registry() -> Vec<AppEntry> {
+    vec![
+        AppEntry::of::<ta1lsd003::TA1LSD003>("ta1lsd003"),
+        AppEntry::of::<ta1lsd005::TA1LSD005>("ta1lsd005"),
+        AppEntry::of::<free99::BULLETINMYBRAIN>("bulletinmybrain"),
+    ]
+}
+
+pub fn find(name: &str) -> Option<AppEntry> {
+    registry()
+        .into_iter()
+        .find(|entry| entry.name.eq_ignore_ascii_case(name))
+}
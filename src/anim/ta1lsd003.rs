@@ -1,17 +1,13 @@
 use std::{
     f32::consts::{PI as PI32, TAU as TAU32},
     f64::consts::PI as PI64,
-    fs::File,
     sync::Arc,
-    time::Duration,
 };
 
 use anyhow::Result;
 use data::Pipelines;
-use glam::{vec2, vec3, Mat4, Quat, Vec2, Vec3, Vec4};
-use rand::{thread_rng, Rng, SeedableRng};
-use rand_chacha::ChaCha8Rng;
-use rodio::{OutputStream, OutputStreamHandle};
+use glam::{vec3, Mat4, Vec3, Vec4};
+use rand::Rng;
 use vulkano::{
     command_buffer::{allocator::CommandBufferAllocator, AutoCommandBufferBuilder},
     device::Device,
@@ -23,10 +19,11 @@ use vulkano::{
 use winit::dpi::PhysicalSize;
 
 use crate::renderer::{
-    app::App,
-    color::{self, Color},
+    app::{App, FrameContext},
+    color,
     ext::CommandBufferExt,
-    misc::{self, SinkExtrapolator},
+    misc,
+    tempo::{Tempo, TempoMap, TimePoint},
     termbuf::{self, TerminalPanel},
 };
 
@@ -36,15 +33,14 @@ mod data {
     use anyhow::Result;
     use vulkano::{
         device::Device,
-        image::sampler::Filter,
         memory::allocator::MemoryAllocator,
-        pipeline::{graphics::viewport::Viewport, GraphicsPipeline},
+        pipeline::GraphicsPipeline,
         render_pass::RenderPass,
     };
 
     use crate::{
         create_graphics_pipeline,
-        renderer::{ext::CommandBufferExt, mesh},
+        renderer::ext::CommandBufferExt,
     };
 
     use super::*;
@@ -56,7 +52,6 @@ mod data {
         pub fn new(
             device: Arc<Device>,
             render_pass: Arc<RenderPass>,
-            viewport: Viewport,
         ) -> Result<Self> {
             Ok(Self {
                 terminal_pipeline: create_graphics_pipeline(
@@ -64,7 +59,6 @@ mod data {
                     termbuf::shaders::vertex::load(device.clone())?,
                     termbuf::shaders::fragment::load(device)?,
                     render_pass,
-                    viewport,
                 )?,
             })
         }
@@ -73,9 +67,9 @@ mod data {
     pub struct Meshes {}
     impl Meshes {
         pub fn new(
-            loader_commands: &mut impl CommandBufferExt,
-            allocator: Arc<dyn MemoryAllocator>,
-            device: Arc<Device>,
+            _loader_commands: &mut impl CommandBufferExt,
+            _allocator: Arc<dyn MemoryAllocator>,
+            _device: Arc<Device>,
         ) -> Result<Self> {
             Ok(Self {
                 // duct: Mesh::load_gltf(
@@ -92,7 +86,7 @@ mod data {
 
 fn second_drop_write(to: &mut [u8], beat: f64) {
     to.fill(0);
-    const DATA: [&'static str; 16] = [
+    const DATA: [&str; 16] = [
         "ALL", "ALL\nTHE", "ALL\nTHE\nTHINGS", "ALL\nTHE\nTHINGS\nSHE",
         "THINGS", "THINGS\nSHE", "THINGS\nSHE\nSAID", "THINGS\nSHE\nSAID",
         "\nALL", "\nALL", "\nALL\nRUN", "\nALL\nRUNNING",
@@ -117,52 +111,32 @@ fn second_drop_write(to: &mut [u8], beat: f64) {
     }
 }
 
-enum TimeBase {
-    RealTime(Duration),
-    Audio(SinkExtrapolator),
-}
-impl TimeBase {
-    pub fn rt_step(&mut self, delta: Duration) {
-        if let TimeBase::RealTime(s) = self {
-            *s += delta;
-        }
-    }
-    pub fn get_pos(&mut self) -> Duration {
-        match self {
-            Self::RealTime(duration) => *duration,
-            Self::Audio(audio) => audio.get_pos(),
-        }
-    }
-}
+const TEMPO: Tempo = Tempo::new(100.0, 0.0134);
 
 pub struct TA1LSD003 {
-    _output_stream: (OutputStream, OutputStreamHandle),
-    time: TimeBase,
     beat: f64,
-    bpm: f64,
-    offset: f64,
-    start: f64,
 
     panel: TerminalPanel,
     tunnel: TerminalPanel,
     tunnel_words: TerminalPanel,
 
-    device: Arc<Device>,
-
     pipelines: Arc<Pipelines>,
 }
 impl App for TA1LSD003 {
     const INITIAL_SIZE: PhysicalSize<u32> = PhysicalSize::new(9 * 128, 16 * 64);
+    // the second drop
+    const START: TimePoint = TimePoint::Beats(31.9);
+    const END: Option<TimePoint> = Some(TimePoint::Beats(64.0));
 
     fn new<L, A: CommandBufferAllocator + 'static>(
         loader_command_buffer: &mut AutoCommandBufferBuilder<L, A>,
         allocator: Arc<dyn MemoryAllocator>,
         device: Arc<Device>,
         render_pass: Arc<RenderPass>,
-        viewport: Viewport,
+        _viewport: Viewport,
     ) -> Result<Self> {
         let (_, charset) = loader_command_buffer.load_image(
-            "charset.png",
+            termbuf::charset(),
             allocator.clone(),
             ImageUsage::SAMPLED,
         )?;
@@ -216,42 +190,20 @@ impl App for TA1LSD003 {
             termbuf::PANEL_INDICES.into_iter(),
         )?;
 
-        let bpm = 100.0;
-        let offset = 0.0134;
-        let first_drop = 28.0;
-        let second_drop = 31.9;
-
-        let start = second_drop * 60.0 / bpm + offset;
-        println!("{start}");
-
-        let (_os, osh) = OutputStream::try_default()?;
-        // let sink = osh.play_once(File::open("ta1lsd003.mp3")?)?;
-        // let extrapolator = SinkExtrapolator::new(sink);
-        // extrapolator
-        //     .sink
-        //     .try_seek(Duration::from_secs_f64(start))
-        //     .unwrap();
-
         Ok(Self {
             beat: 0.0,
-            _output_stream: (_os, osh),
-            time: TimeBase::RealTime(Duration::from_secs_f64(start)),//extrapolator,
             panel,
             tunnel,
             tunnel_words,
-            bpm,
-            offset,
-            start,
-            device: device.clone(),
-            pipelines: Arc::new(Pipelines::new(device, render_pass, viewport)?),
+            pipelines: Arc::new(Pipelines::new(device, render_pass)?),
         })
     }
     fn update<L, A: CommandBufferAllocator>(
         &mut self,
+        context: &mut FrameContext,
         upload_command_buffer: &mut AutoCommandBufferBuilder<L, A>,
     ) -> Result<()> {
-        let secs = self.time.get_pos().as_secs_f64();
-        self.beat = (secs - self.offset) * self.bpm / 60.0;
+        self.beat = context.beat;
 
         let text = "   TA1LSD003   ";
         let text_length = text.len();
@@ -301,8 +253,7 @@ impl App for TA1LSD003 {
                         let bw = beat_m2 > 1.0 && beat_m2 < 1.25;
                         let flip = ((x as f64 * xfac + (beat * 32.0).sin() * 64.0 + 64.0) as usize
                             + (y as f64 * yfac + (beat * 32.0).cos() * 64.0 + 64.0) as usize)
-                            % 2
-                            == 0;
+                            .is_multiple_of(2);
                         if bw {
                             ch[i] = 0;
                             bg[i] = if flip { color::WHITE } else { color::BLACK };
@@ -330,32 +281,30 @@ impl App for TA1LSD003 {
             let mut ch = self.tunnel.character_buffer.write()?;
             for i in 0..self.tunnel.width() as usize * self.tunnel.height() as usize {
                 bg[i] = Vec4::new(
-                    thread_rng().gen(),
-                    thread_rng().gen(),
-                    thread_rng().gen(),
+                    context.rng.gen(),
+                    context.rng.gen(),
+                    context.rng.gen(),
                     1.0,
                 );
                 fg[i] = Vec4::new(
-                    thread_rng().gen(),
-                    thread_rng().gen(),
-                    thread_rng().gen(),
+                    context.rng.gen(),
+                    context.rng.gen(),
+                    context.rng.gen(),
                     1.0,
                 );
-                ch[i] = thread_rng().gen();
+                ch[i] = context.rng.gen();
             }
             self.tunnel.update(upload_command_buffer);
         }
 
-        self.time.rt_step(Duration::from_secs_f64(1.0 / 60.0));
-        println!("{}", self.beat);
-
         Ok(())
     }
     fn draw<L, A: CommandBufferAllocator>(
         &mut self,
+        context: &mut FrameContext,
         render_command_buffer: &mut AutoCommandBufferBuilder<L, A>,
     ) -> Result<()> {
-        let aspect = Self::INITIAL_SIZE.width as f32 / Self::INITIAL_SIZE.height as f32;
+        let aspect = context.aspect();
         render_command_buffer.bind_pipeline_graphics(self.pipelines.terminal_pipeline.clone())?;
         let transform = Mat4::perspective_lh(PI32 / 2.0, aspect, 0.01, 100.0)
             * Mat4::look_at_lh(Vec3::Z * 20.0, Vec3::ZERO, Vec3::Y);
@@ -364,7 +313,7 @@ impl App for TA1LSD003 {
             self.panel.draw(
                 render_command_buffer,
                 &self.pipelines.terminal_pipeline,
-                self.device.clone(),
+                context.device.clone(),
                 Mat4::IDENTITY,
             )?;
         }
@@ -372,13 +321,13 @@ impl App for TA1LSD003 {
             self.tunnel_words.draw(
                 render_command_buffer,
                 &self.pipelines.terminal_pipeline,
-                self.device.clone(),
-                Mat4::from_translation(vec3(thread_rng().gen_range(-0.02..0.02), thread_rng().gen_range(-0.02..0.02), 0.0)),
+                context.device.clone(),
+                Mat4::from_translation(vec3(context.rng.gen_range(-0.02..0.02), context.rng.gen_range(-0.02..0.02), 0.0)),
             )?;
             self.tunnel.draw(
                 render_command_buffer,
                 &self.pipelines.terminal_pipeline,
-                self.device.clone(),
+                context.device.clone(),
                 transform
                     * Mat4::from_translation(vec3(0.0, 0.0, (self.beat % 1.0 * 35.0) as f32))
                     * Mat4::from_rotation_z(self.beat as f32 * 10.0),
@@ -386,13 +335,10 @@ impl App for TA1LSD003 {
         }
         Ok(())
     }
-    fn resize(&mut self, new_size: PhysicalSize<u32>) -> Result<()> {
-        Ok(())
-    }
-    fn done(&self) -> bool {
-        self.beat > 64.0
+    fn tempo() -> Result<Option<TempoMap>> {
+        Ok(Some(TEMPO.into()))
     }
-    fn audio(&self) -> Option<(&'static str, f64)> {
-        Some(("ta1lsd003.mp3", self.start))
+    fn audio(&self) -> Option<&'static str> {
+        Some("ta1lsd003.mp3")
     }
 }
@@ -1,15 +1,13 @@
-use std::{f32::consts::PI, fs::File, sync::Arc, time::Duration};
+use std::{f32::consts::PI, sync::Arc};
 
 use anyhow::Result;
 use data::Pipelines;
 use glam::{vec2, vec3, Mat4, Quat, Vec2, Vec3};
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
-use rodio::{OutputStream, OutputStreamHandle};
 use vulkano::{command_buffer::{allocator::CommandBufferAllocator, AutoCommandBufferBuilder}, device::Device, image::ImageUsage, memory::allocator::MemoryAllocator, pipeline::graphics::viewport::Viewport, render_pass::RenderPass};
-use winit::dpi::PhysicalSize;
 
-use crate::renderer::{app::App, color, ext::CommandBufferExt, misc::SinkExtrapolator, termbuf::{self, TerminalPanel}};
+use crate::renderer::{app::{App, FrameContext}, color, ext::CommandBufferExt, keyframe::{Easing, Extrapolation, KeyframeSequence}, tempo::{Tempo, TempoMap, TimePoint}, termbuf::{self, TerminalPanel}};
 
 mod data {
     use std::sync::Arc;
@@ -17,13 +15,12 @@ mod data {
     use anyhow::Result;
     use vulkano::{
         device::Device,
-        image::sampler::Filter,
         memory::allocator::MemoryAllocator,
-        pipeline::{graphics::viewport::Viewport, GraphicsPipeline},
+        pipeline::GraphicsPipeline,
         render_pass::RenderPass,
     };
 
-    use crate::{create_graphics_pipeline, renderer::{ext::CommandBufferExt, mesh}};
+    use crate::{create_graphics_pipeline, renderer::ext::CommandBufferExt};
 
     use super::*;
 
@@ -34,7 +31,6 @@ mod data {
         pub fn new(
             device: Arc<Device>,
             render_pass: Arc<RenderPass>,
-            viewport: Viewport,
         ) -> Result<Self> {
             Ok(Self {
                 terminal_pipeline: create_graphics_pipeline(
@@ -42,7 +38,6 @@ mod data {
                     termbuf::shaders::vertex::load(device.clone())?,
                     termbuf::shaders::fragment::load(device)?,
                     render_pass,
-                    viewport,
                 )?,
             })
         }
@@ -51,9 +46,9 @@ mod data {
     pub struct Meshes {}
     impl Meshes {
         pub fn new(
-            loader_commands: &mut impl CommandBufferExt,
-            allocator: Arc<dyn MemoryAllocator>,
-            device: Arc<Device>,
+            _loader_commands: &mut impl CommandBufferExt,
+            _allocator: Arc<dyn MemoryAllocator>,
+            _device: Arc<Device>,
         ) -> Result<Self> {
             Ok(Self {
                 // duct: Mesh::load_gltf(
@@ -68,28 +63,30 @@ mod data {
     }
 }
 
+const TEMPO: Tempo = Tempo::new(115.0, 0.0134);
+
 pub struct TA1LSD005 {
-    _output_stream: (OutputStream, OutputStreamHandle),
-    audio: SinkExtrapolator,
     beat: f64,
 
     title: Vec<TerminalPanel>,
     ring: Vec<TerminalPanel>,
-
-    device: Arc<Device>,
+    // how visible each arm is, over one of its cycles
+    ring_fade: KeyframeSequence<f32>,
 
     pipelines: Arc<Pipelines>,
 }
 impl App for TA1LSD005 {
+    const START: TimePoint = TimePoint::Beats(30.0);
+
     fn new<L, A: CommandBufferAllocator + 'static>(
         loader_command_buffer: &mut AutoCommandBufferBuilder<L, A>,
         allocator: Arc<dyn MemoryAllocator>,
         device: Arc<Device>,
         render_pass: Arc<RenderPass>,
-        viewport: Viewport,
+        _viewport: Viewport,
     ) -> Result<Self> {
         let (_, charset) = loader_command_buffer.load_image(
-            "charset.png",
+            termbuf::charset(),
             allocator.clone(),
             ImageUsage::SAMPLED,
         )?;
@@ -134,34 +131,23 @@ impl App for TA1LSD005 {
             })
             .collect::<Result<Vec<TerminalPanel>>>()?;
 
-        let (_os, osh) = OutputStream::try_default()?;
-        let sink = osh.play_once(File::open("ta1lsd005.mp3")?)?;
-        let extrapolator = SinkExtrapolator::new(sink);
-        let bpm = 115.0;
-        let offset = 0.0134;
-        extrapolator
-            .sink
-            .try_seek(Duration::from_secs_f32(30.0 * 60.0 / bpm + offset))
-            .unwrap();
-
         Ok(Self {
             beat: 0.0,
-            _output_stream: (_os, osh),
-            audio: extrapolator,
             title,
             ring,
-            device: device.clone(),
-            pipelines: Arc::new(Pipelines::new(device, render_pass, viewport)?)
+            ring_fade: KeyframeSequence::new()
+                .key(0.0, 1.0, Easing::linear())
+                .key(1.0, 0.0, Easing::linear())
+                .extrapolate(Extrapolation::Loop),
+            pipelines: Arc::new(Pipelines::new(device, render_pass)?)
         })
     }
     fn update<L, A: CommandBufferAllocator>(
         &mut self,
+        context: &mut FrameContext,
         upload_command_buffer: &mut AutoCommandBufferBuilder<L, A>,
     ) -> Result<()> {
-        let secs = self.audio.get_pos().as_secs_f64();
-        let bpm = 115.0;
-        let offset = 0.0134;
-        self.beat = (secs - offset) * bpm / 60.0;
+        self.beat = context.beat;
         // let active_panel = self.beat as usize % 7;
 
         let mut rng = ChaCha8Rng::seed_from_u64(0);
@@ -170,9 +156,9 @@ impl App for TA1LSD005 {
         for (index, panel) in self.title.iter_mut().enumerate() {
             panel.flat_transform(
                 vec3(
-                    (rng.gen::<f32>() - 0.5) * (title_beat as f32 * 2.0).powi(2) * 0.2
+                    (rng.gen::<f32>() - 0.5) * (title_beat * 2.0).powi(2) * 0.2
                         + (index as f32 - title_length as f32 / 2.0) * 0.18,
-                    (rng.gen::<f32>() - 0.5) * (title_beat as f32 * 2.0).powi(2) * 0.2,
+                    (rng.gen::<f32>() - 0.5) * (title_beat * 2.0).powi(2) * 0.2,
                     0.0,
                 ),
                 Quat::IDENTITY,
@@ -190,7 +176,7 @@ impl App for TA1LSD005 {
             let speed = (index % 4 + 1) as f32;
             let mut color =
                 color::sinebow(index as f32 / arm_count as f32 + ring_beat).map(|f| f * 0.5 + 0.5);
-            color[3] = 1.0 - (ring_beat * speed / 4.0).rem_euclid(1.0);
+            color[3] = self.ring_fade.sample(ring_beat * speed / 4.0);
             panel.fill_fg(color)?;
             for (ch_index, character) in panel.character_buffer.write()?.iter_mut().enumerate() {
                 let ch = b"-\\|/"[((ring_beat * 8.0) as usize + index + ch_index) % 4];
@@ -202,6 +188,7 @@ impl App for TA1LSD005 {
     }
     fn draw<L, A: CommandBufferAllocator>(
         &mut self,
+        context: &mut FrameContext,
         render_command_buffer: &mut AutoCommandBufferBuilder<L, A>,
     ) -> Result<()> {
         let title_beat = self.beat as f32 - 31.0;
@@ -212,7 +199,7 @@ impl App for TA1LSD005 {
         // let eye = vec3(t.sin() as f32, 0.0, t.cos() as f32) * 3.0;
         // let target = vec3(0.0, 0.0, 0.0);
 
-        let aspect = Mat4::from_scale(vec3(900.0 / 1600.0, 1.0, 1.0));
+        let aspect = Mat4::from_scale(vec3(1.0 / context.aspect(), 1.0, 1.0));
 
         let title_transform = aspect
             * Mat4::from_scale(
@@ -224,7 +211,7 @@ impl App for TA1LSD005 {
                     .draw(
                         render_command_buffer,
                         &self.pipelines.terminal_pipeline,
-                        self.device.clone(),
+                        context.device.clone(),
                         title_transform,
                     )
                     .unwrap();
@@ -240,8 +227,8 @@ impl App for TA1LSD005 {
                 arm.draw(
                     render_command_buffer,
                     &self.pipelines.terminal_pipeline,
-                    self.device.clone(),
-                    aspect * Mat4::from_rotation_z((ring_beat as f32 * speed / 16.0) * PI),
+                    context.device.clone(),
+                    aspect * Mat4::from_rotation_z((ring_beat * speed / 16.0) * PI),
                 )
                 .unwrap();
             }
@@ -249,7 +236,10 @@ impl App for TA1LSD005 {
 
         Ok(())
     }
-    fn resize(&mut self, new_size: PhysicalSize<u32>) -> Result<()> {
-        Ok(())
+    fn tempo() -> Result<Option<TempoMap>> {
+        Ok(Some(TEMPO.into()))
+    }
+    fn audio(&self) -> Option<&'static str> {
+        Some("ta1lsd005.mp3")
     }
 }
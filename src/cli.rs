@@ -0,0 +1,258 @@
+use anyhow::{anyhow, bail, Context, Result};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    project::Project,
+    renderer::{
+        clock::FrameRate, encoder::EncoderSettings, output::OutputTransform, sink::SinkKind,
+        tempo::TimePoint,
+    },
+};
+
+pub const USAGE: &str = "\
+usage:
+    vulkan_experiments render <app> [options]
+    vulkan_experiments render <project.toml> [options]
+    vulkan_experiments list
+
+a project file puts apps on a timeline with their own audio, tempo and render settings; see
+`Project` for the format.
+
+render options:
+    --out <file>          where the finished video is written (default: done.mp4)
+    --fps <n>             frames per second, like 120, 29.97 or 30000/1001 (default: 60)
+    --size <w>x<h>        output size (default: the app's initial size)
+    --window-size <w>x<h> size of the preview window; frames are scaled to fit it (default: the
+                          output size)
+    --supersample <n>     render n times as large in each direction, and box filter each frame
+                          back down to the output size (default: 1)
+    --msaa <n>            samples per pixel: 1, 2, 4 or 8, lowered to whatever the device
+                          supports (default: 1)
+    --headless            render without a window; works on machines without a display
+    --preview             play the piece in real time with its audio, without recording
+                          anything. space pauses, the arrow keys skip a second either way, and
+                          R starts and stops recording; each recording replaces the last, and
+                          plays back at the frame rate instead of in real time
+    --from <time>         where to start rendering, in seconds (12.5 or 12.5s), beats (32b) or
+                          bars:beats:ticks (8:0 or 8:2:240); the audio is cut to match
+                          (default: where the app starts, usually the start of the piece)
+    --to <time>           where to stop rendering (default: wherever the app or piece ends)
+    --tempo-map <file>    where the beats fall, for tracks that change tempo or meter; see
+                          `TempoMap::parse` for the format (default: the app's own tempo)
+    --sink <kind>         where frames go: video (through ffmpeg, the default), png, png16, exr,
+                          y4m, y4m444 or avi. y4m and avi are written next to --out and don't
+                          need ffmpeg. can be given more than once to write several at once
+    --frames <pattern>    file name of each frame for image sinks, with a run of #s replaced by
+                          the frame number (default: frames/frame_######.<ext>)
+    --readback-depth <n>  how many frames can be read back and written out while the next ones
+                          render (default: 3). each one costs a full size float image and
+                          staging buffer
+
+output options (everything but exr):
+    --encoding <name>     `display` (the default) writes rendered values as they are; `srgb`
+                          treats them as linear light and applies the sRGB curve
+    --tonemap <name>      how values above 1 are handled: clamp (the default), reinhard or aces
+    --dither <name>       none (the default), ordered or blue-noise; hides banding in 8 bit output
+
+encoder options:
+    --profile <name>      `preview` (fast, the default) or `final` (high quality)
+    --codec <name>        ffmpeg video codec, like libx264 or libx265
+    --crf <n>             constant rate factor
+    --bitrate <rate>      target video bitrate, like 20M; replaces --crf
+    --preset <name>       encoder speed preset, or `none`
+    --pix-fmt <name>      encoded pixel format, like yuv420p or yuv444p
+    --container <name>    ffmpeg muxer (default: guessed from --out)
+    --audio-codec <name>  ffmpeg audio codec, like aac or copy
+    --audio-bitrate <rate>
+";
+
+pub enum Command {
+    Render(Box<RenderOptions>),
+    List,
+    Help,
+}
+
+#[derive(Clone)]
+pub struct RenderOptions {
+    pub app: String,
+    /// What the clock steps by while recording, and what the video is encoded at.
+    pub frame_rate: FrameRate,
+    pub encoder: EncoderSettings,
+    pub size: Option<PhysicalSize<u32>>,
+    pub window_size: Option<PhysicalSize<u32>>,
+    pub supersample: u32,
+    pub msaa: u32,
+    pub headless: bool,
+    pub preview: bool,
+    pub from: Option<TimePoint>,
+    pub to: Option<TimePoint>,
+    pub tempo_map: Option<String>,
+    pub sinks: Vec<SinkKind>,
+    pub frame_pattern: Option<String>,
+    pub readback_depth: usize,
+    pub output: OutputTransform,
+    pub project: Option<Project>,
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command> {
+    let mut args = args.into_iter();
+    let Some(subcommand) = args.next() else {
+        return Ok(Command::Help);
+    };
+    match subcommand.as_str() {
+        "render" => {
+            let args: Vec<String> = args.collect();
+            let project = match find_app(&args) {
+                Some(path) if path.ends_with(".toml") => Some(Project::load(path)?),
+                _ => None,
+            };
+            // a project's settings come first, so anything on the command line overrides them.
+            // switches like `--headless` can only be turned on, so a project that sets them
+            // can't have them turned off here
+            let project_args = match &project {
+                Some(project) => project.args(&args)?,
+                None => Vec::new(),
+            };
+            let mut options = parse_render(project_args.into_iter().chain(args))?;
+            options.project = project;
+            Ok(Command::Render(Box::new(options)))
+        }
+        "list" => Ok(Command::List),
+        "help" | "-h" | "--help" => Ok(Command::Help),
+        other => bail!("unknown command `{other}`"),
+    }
+}
+
+fn parse_render(mut args: impl Iterator<Item = String>) -> Result<RenderOptions> {
+    let mut app = None;
+    let mut output = "done.mp4".to_string();
+    let mut profile = "preview".to_string();
+    // applied on top of the profile once everything is parsed, so flag order doesn't matter
+    let mut encoder_overrides = Vec::new();
+    let mut options = RenderOptions {
+        app: String::new(),
+        frame_rate: FrameRate::default(),
+        encoder: EncoderSettings::preview(""),
+        size: None,
+        window_size: None,
+        supersample: 1,
+        msaa: 1,
+        headless: false,
+        preview: false,
+        from: None,
+        to: None,
+        tempo_map: None,
+        sinks: Vec::new(),
+        frame_pattern: None,
+        readback_depth: 3,
+        output: OutputTransform::default(),
+        project: None,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => output = value(&mut args, &arg)?,
+            "--profile" => profile = value(&mut args, &arg)?,
+            "--fps" => options.frame_rate = FrameRate::parse(&value(&mut args, &arg)?)?,
+            "--codec" | "--crf" | "--bitrate" | "--preset" | "--pix-fmt" | "--container"
+            | "--audio-codec" | "--audio-bitrate" => {
+                encoder_overrides.push((arg[2..].to_string(), value(&mut args, &arg)?))
+            }
+            "--size" => options.size = Some(parse_size(&value(&mut args, &arg)?)?),
+            "--window-size" => options.window_size = Some(parse_size(&value(&mut args, &arg)?)?),
+            "--supersample" => {
+                let factor = value(&mut args, &arg)?;
+                options.supersample = factor
+                    .parse()
+                    .ok()
+                    .filter(|&factor| factor > 0)
+                    .ok_or(anyhow!("invalid supersampling factor `{factor}`"))?
+            }
+            "--msaa" => {
+                let samples = value(&mut args, &arg)?;
+                options.msaa = samples
+                    .parse()
+                    .ok()
+                    .filter(|samples| [1, 2, 4, 8].contains(samples))
+                    .ok_or(anyhow!(
+                        "invalid msaa sample count `{samples}`; expected 1, 2, 4 or 8"
+                    ))?
+            }
+            "--headless" => options.headless = true,
+            "--preview" => options.preview = true,
+            "--from" => options.from = Some(TimePoint::parse(&value(&mut args, &arg)?)?),
+            "--to" => options.to = Some(TimePoint::parse(&value(&mut args, &arg)?)?),
+            "--tempo-map" => options.tempo_map = Some(value(&mut args, &arg)?),
+            "--sink" => options
+                .sinks
+                .push(SinkKind::parse(&value(&mut args, &arg)?)?),
+            "--frames" => options.frame_pattern = Some(value(&mut args, &arg)?),
+            "--readback-depth" => {
+                let depth = value(&mut args, &arg)?;
+                options.readback_depth = depth
+                    .parse()
+                    .ok()
+                    .filter(|&depth| depth > 0)
+                    .ok_or(anyhow!("invalid readback depth `{depth}`"))?
+            }
+            "--encoding" | "--tonemap" | "--dither" => {
+                options.output.set(&arg[2..], &value(&mut args, &arg)?)?
+            }
+            flag if flag.starts_with("--") => bail!("unknown option `{flag}`"),
+            _ if app.is_none() => app = Some(arg),
+            _ => bail!("unexpected argument `{arg}`"),
+        }
+    }
+
+    options.app = app.ok_or(anyhow!("no app given to render"))?;
+    if options.preview && options.headless {
+        bail!("a preview needs a window, so it can't be headless");
+    }
+    if options.sinks.is_empty() {
+        options.sinks.push(SinkKind::Video);
+    }
+    options.encoder = EncoderSettings::from_profile(&profile, output)?;
+    for (key, value) in encoder_overrides {
+        options.encoder.set(&key, &value)?;
+    }
+    Ok(options)
+}
+
+/// The app argument, without parsing anything else.
+fn find_app(args: &[String]) -> Option<&str> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // the only flags without a value
+            "--headless" | "--preview" => {}
+            flag if flag.starts_with("--") => {
+                args.next();
+            }
+            app => return Some(app),
+        }
+    }
+    None
+}
+
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String> {
+    args.next()
+        .ok_or(anyhow!("expected a value after `{flag}`"))
+}
+
+pub fn parse_size(text: &str) -> Result<PhysicalSize<u32>> {
+    let (width, height) = text
+        .split_once('x')
+        .ok_or(anyhow!("expected a size like 1920x1080, got `{text}`"))?;
+    let size = PhysicalSize::new(
+        width
+            .parse()
+            .with_context(|| format!("invalid width in `{text}`"))?,
+        height
+            .parse()
+            .with_context(|| format!("invalid height in `{text}`"))?,
+    );
+    if size.width == 0 || size.height == 0 {
+        bail!("size `{text}` has no area");
+    }
+    Ok(size)
+}
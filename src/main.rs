@@ -1,24 +1,34 @@
+// pieces in progress keep scaffolding around that they don't use yet
+#[allow(dead_code)]
 mod anim;
+mod cli;
+mod project;
+// a toolkit for the pieces in `anim`, which don't all use every part of it
+#[allow(dead_code)]
 mod renderer;
 
-use std::{
-    io::{self, Write},
-    process::{Child, ChildStdin, Command, Stdio},
-    sync::Arc,
-};
+use std::{collections::HashSet, path::Path, sync::Arc};
 
-use anim::{free99::BULLETINMYBRAIN, ta1lsd003::TA1LSD003, ta1lsd005::TA1LSD005};
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use cli::{Command, RenderOptions};
 use glam::Mat4;
-use image::{Rgba32FImage, RgbaImage};
-use renderer::{app::App, ext::CommandBufferExt, vertex};
+use renderer::{
+    app::{AppEntry, DynApp, FrameContext},
+    clock::{Clock, FixedStep, Transport},
+    rawvideo::{AviSink, Y4mSink},
+    recorder::Recorder,
+    sink::{FrameSink, ImageSequenceSink, SinkKind, VideoSink},
+    target::{begin_render_pass, RenderTarget},
+    tempo::TempoMap,
+    termbuf, vertex,
+    window::WindowTarget,
+};
 use vulkano::{
-    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage},
+    buffer::BufferContents,
     command_buffer::{
         allocator::{StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo},
-        AutoCommandBufferBuilder, CommandBufferUsage, CopyImageInfo, CopyImageToBufferInfo,
-        PrimaryAutoCommandBuffer, PrimaryCommandBufferAbstract, RenderPassBeginInfo,
-        SubpassBeginInfo, SubpassContents, SubpassEndInfo,
+        AutoCommandBufferBuilder, CommandBufferUsage, PrimaryAutoCommandBuffer,
+        PrimaryCommandBufferAbstract, SubpassEndInfo,
     },
     descriptor_set::{
         allocator::StandardDescriptorSetAllocator, PersistentDescriptorSet, WriteDescriptorSet,
@@ -28,11 +38,9 @@ use vulkano::{
         Device, DeviceCreateInfo, DeviceExtensions, Queue, QueueCreateInfo, QueueFlags,
     },
     format::Format,
-    image::{view::ImageView, Image, ImageCreateInfo, ImageType, ImageUsage},
+    image::{Image, SampleCount},
     instance::{Instance, InstanceCreateFlags, InstanceCreateInfo},
-    memory::allocator::{
-        AllocationCreateInfo, MemoryAllocator, MemoryTypeFilter, StandardMemoryAllocator,
-    },
+    memory::allocator::{MemoryAllocator, StandardMemoryAllocator},
     pipeline::{
         graphics::{
             color_blend::{
@@ -47,84 +55,20 @@ use vulkano::{
             GraphicsPipelineCreateInfo,
         },
         layout::PipelineDescriptorSetLayoutCreateInfo,
-        GraphicsPipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
     },
-    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+    render_pass::{Framebuffer, RenderPass, Subpass},
     shader::ShaderModule,
-    swapchain::{PresentMode, Surface, Swapchain, SwapchainCreateInfo, SwapchainPresentInfo},
+    swapchain::{PresentMode, Surface},
     sync::{self, future::FenceSignalFuture, GpuFuture},
-    Validated, VulkanError, VulkanLibrary,
+    VulkanLibrary,
 };
 use winit::{
-    dpi::PhysicalSize,
-    event::{Event, WindowEvent},
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
 
-fn ffmpeg_video_stream(
-    width: usize,
-    height: usize,
-    file: impl AsRef<str>,
-) -> Result<(Child, ChildStdin)> {
-    let mut child = Command::new("ffmpeg")
-        .args([
-            "-f",
-            "rawvideo",
-            "-pix_fmt",
-            "rgba",
-            "-s:v",
-            &format!("{width}x{height}"),
-            "-r",
-            "60",
-            "-i",
-            "pipe:",
-            "-c:v",
-            "libx264",
-            "-preset",
-            "ultrafast",
-            "-y",
-            file.as_ref(),
-        ])
-        .stdin(Stdio::piped())
-        // .stdout(Stdio::null())
-        // .stdin(Stdio::null())
-        .spawn()?;
-    let pixel_input = child.stdin.take().unwrap();
-    Ok((child, pixel_input))
-}
-fn merge_av(
-    audio: impl AsRef<str>,
-    audio_offset: f64,
-    video: impl AsRef<str>,
-    output: impl AsRef<str>,
-) -> Result<(Child, ChildStdin)> {
-    let mut child = Command::new("ffmpeg")
-        .args([
-            "-ss",
-            "0.0",
-            "-i",
-            video.as_ref(),
-            "-ss",
-            &format!("{audio_offset}"),
-            "-i",
-            audio.as_ref(),
-            "-c:v",
-            "copy",
-            "-c:a",
-            "mp3",
-            "-shortest",
-            "-y",
-            output.as_ref(),
-        ])
-        .stdin(Stdio::piped())
-        // .stdout(Stdio::null())
-        // .stdin(Stdio::null())
-        .spawn()?;
-    let pixel_input = child.stdin.take().unwrap();
-    Ok((child, pixel_input))
-}
-
 #[derive(BufferContents)]
 #[repr(C)]
 struct TransformUBO {
@@ -138,22 +82,26 @@ impl TransformUBO {
     }
 }
 
+/// Picks the best device with a graphics queue. If `surface` is `None` (headless rendering),
+/// surface support isn't required, so software implementations like lavapipe are accepted too.
 fn get_physical_device(
     instance: &Arc<Instance>,
-    surface: &Arc<Surface>,
+    surface: Option<&Arc<Surface>>,
     device_extensions: &DeviceExtensions,
 ) -> (Arc<PhysicalDevice>, u32) {
     instance
         .enumerate_physical_devices()
         .expect("could not enumerate devices")
-        .filter(|p| p.supported_extensions().contains(&device_extensions))
+        .filter(|p| p.supported_extensions().contains(device_extensions))
         .filter_map(|p| {
             p.queue_family_properties()
                 .iter()
                 .enumerate()
                 .position(|(i, q)| {
                     q.queue_flags.contains(QueueFlags::GRAPHICS)
-                        && p.surface_support(i as u32, &surface).unwrap_or(false)
+                        && surface.is_none_or(|surface| {
+                            p.surface_support(i as u32, surface).unwrap_or(false)
+                        })
                 })
                 .map(|q| (p, q as u32))
         })
@@ -167,7 +115,63 @@ fn get_physical_device(
         .expect("no device available")
 }
 
-fn create_render_pass(device: Arc<Device>, format: Format) -> Result<Arc<RenderPass>> {
+/// The most samples per pixel, up to `requested`, that this device can render both color and depth
+/// with.
+fn clamp_sample_count(physical_device: &PhysicalDevice, requested: u32) -> SampleCount {
+    let properties = physical_device.properties();
+    let supported =
+        properties.framebuffer_color_sample_counts & properties.framebuffer_depth_sample_counts;
+    let samples = [8, 4, 2]
+        .into_iter()
+        .filter(|&samples| samples <= requested)
+        .map(|samples| SampleCount::try_from(samples).unwrap())
+        .find(|&samples| supported.contains_enum(samples))
+        .unwrap_or(SampleCount::Sample1);
+    if u32::from(samples) < requested {
+        println!(
+            "this device can't do {requested}x msaa, using {}x",
+            u32::from(samples)
+        );
+    }
+    samples
+}
+
+fn create_render_pass(
+    device: Arc<Device>,
+    format: Format,
+    samples: SampleCount,
+) -> Result<Arc<RenderPass>> {
+    if samples != SampleCount::Sample1 {
+        // everything is drawn multisampled, then resolved into the actual image at the end
+        return Ok(vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                color: {
+                    format: format,
+                    samples: samples,
+                    load_op: Clear,
+                    store_op: DontCare,
+                },
+                depth_stencil: {
+                    format: Format::D16_UNORM,
+                    samples: samples,
+                    load_op: Clear,
+                    store_op: DontCare,
+                },
+                resolve: {
+                    format: format,
+                    samples: 1,
+                    load_op: DontCare,
+                    store_op: Store,
+                }
+            },
+            pass: {
+                color: [color],
+                color_resolve: [resolve],
+                depth_stencil: {depth_stencil},
+            },
+        )?);
+    }
     Ok(vulkano::single_pass_renderpass!(
         device.clone(),
         attachments: {
@@ -191,43 +195,11 @@ fn create_render_pass(device: Arc<Device>, format: Format) -> Result<Arc<RenderP
     )?)
 }
 
-fn create_framebuffers(
-    images: &[Arc<Image>],
-    render_pass: &Arc<RenderPass>,
-    allocator: Arc<dyn MemoryAllocator>,
-) -> Result<Vec<Arc<Framebuffer>>> {
-    let depth_buffer = ImageView::new_default(Image::new(
-        allocator,
-        ImageCreateInfo {
-            image_type: ImageType::Dim2d,
-            format: Format::D16_UNORM,
-            extent: images[0].extent(),
-            usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
-            ..Default::default()
-        },
-        AllocationCreateInfo::default(),
-    )?)?;
-
-    images
-        .iter()
-        .map(|image| {
-            Ok(Framebuffer::new(
-                render_pass.clone(),
-                FramebufferCreateInfo {
-                    attachments: vec![ImageView::new_default(image.clone())?, depth_buffer.clone()],
-                    ..Default::default()
-                },
-            )?)
-        })
-        .collect::<Result<Vec<Arc<Framebuffer>>>>()
-}
-
 fn create_graphics_pipeline(
     device: Arc<Device>,
     vsh: Arc<ShaderModule>,
     fsh: Arc<ShaderModule>,
     render_pass: Arc<RenderPass>,
-    viewport: Viewport,
 ) -> Result<Arc<GraphicsPipeline>> {
     let vsh_entry = vsh.entry_point("main").unwrap();
     let fsh_entry = fsh.entry_point("main").unwrap();
@@ -250,10 +222,13 @@ fn create_graphics_pipeline(
             stages: stages.into_iter().collect(),
             vertex_input_state: Some(vertex_input_state),
             input_assembly_state: Some(InputAssemblyState::default()),
-            #[allow(deprecated)]
-            viewport_state: Some(ViewportState::viewport_fixed_scissor_irrelevant([viewport])),
+            // set by begin_render_command_buffer, so pipelines work at any size
+            viewport_state: Some(ViewportState::default()),
             rasterization_state: Some(RasterizationState::default()),
-            multisample_state: Some(MultisampleState::default()),
+            multisample_state: Some(MultisampleState {
+                rasterization_samples: subpass.num_samples().unwrap_or(SampleCount::Sample1),
+                ..Default::default()
+            }),
             color_blend_state: Some(ColorBlendState::with_attachment_states(
                 subpass.num_color_attachments(),
                 ColorBlendAttachmentState {
@@ -273,6 +248,9 @@ fn create_graphics_pipeline(
                 depth: Some(DepthState::simple()),
                 ..Default::default()
             }),
+            dynamic_state: [DynamicState::Viewport, DynamicState::Scissor]
+                .into_iter()
+                .collect(),
             subpass: Some(subpass.into()),
             ..GraphicsPipelineCreateInfo::layout(layout)
         },
@@ -310,33 +288,356 @@ fn begin_render_command_buffer(
         queue.queue_family_index(),
         CommandBufferUsage::OneTimeSubmit,
     )?;
+    begin_render_pass(&mut builder, framebuffer, clear_color)?;
+    Ok(builder)
+}
 
-    builder.begin_render_pass(
-        RenderPassBeginInfo {
-            clear_values: vec![Some(clear_color.into()), Some(1.0f32.into())],
-            ..RenderPassBeginInfo::framebuffer(framebuffer)
-        },
-        SubpassBeginInfo {
-            contents: SubpassContents::Inline,
+fn create_device(
+    physical_device: Arc<PhysicalDevice>,
+    queue_family_index: u32,
+    enabled_extensions: DeviceExtensions,
+) -> Result<(Arc<Device>, Arc<Queue>)> {
+    let (device, mut queues) = Device::new(
+        physical_device,
+        DeviceCreateInfo {
+            queue_create_infos: vec![QueueCreateInfo {
+                queue_family_index,
+                ..Default::default()
+            }],
+            enabled_extensions,
             ..Default::default()
         },
     )?;
-    Ok(builder)
+    Ok((device, queues.next().unwrap()))
+}
+
+fn create_app(
+    entry: &AppEntry,
+    command_buffer_allocator: &StandardCommandBufferAllocator,
+    queue: &Arc<Queue>,
+    allocator: Arc<dyn MemoryAllocator>,
+    render_pass: Arc<RenderPass>,
+    viewport: Viewport,
+) -> Result<Box<dyn DynApp>> {
+    let mut loader_command_buffer = AutoCommandBufferBuilder::primary(
+        command_buffer_allocator,
+        queue.queue_family_index(),
+        CommandBufferUsage::OneTimeSubmit,
+    )?;
+
+    let app = (entry.new)(
+        &mut loader_command_buffer,
+        allocator,
+        queue.device().clone(),
+        render_pass,
+        viewport,
+    )?;
+
+    drop(loader_command_buffer.build()?.execute(queue.clone())?);
+    Ok(app)
+}
+
+fn create_sinks(options: &RenderOptions, extent: [u32; 3]) -> Result<Vec<Box<dyn FrameSink>>> {
+    options
+        .sinks
+        .iter()
+        .map(|kind| -> Result<Box<dyn FrameSink>> {
+            Ok(match kind {
+                SinkKind::Video => Box::new(VideoSink::new(
+                    options.encoder.clone(),
+                    options.output,
+                    extent[0],
+                    extent[1],
+                    options.frame_rate,
+                )?),
+                SinkKind::Sequence(format) => Box::new(ImageSequenceSink::new(
+                    *format,
+                    options
+                        .frame_pattern
+                        .clone()
+                        .unwrap_or_else(|| format!("frames/frame_######.{}", format.extension())),
+                    options.output,
+                )?),
+                SinkKind::Y4m(chroma) => Box::new(Y4mSink::new(
+                    Path::new(&options.encoder.output)
+                        .with_extension("y4m")
+                        .to_string_lossy(),
+                    extent[0],
+                    extent[1],
+                    options.frame_rate,
+                    *chroma,
+                    options.output,
+                )?),
+                SinkKind::Avi => Box::new(AviSink::new(
+                    Path::new(&options.encoder.output)
+                        .with_extension("avi")
+                        .to_string_lossy(),
+                    extent[0],
+                    extent[1],
+                    options.frame_rate,
+                    options.output,
+                )?),
+            })
+        })
+        .collect()
+}
+
+/// The piece's tempo map, unless another one was given on the command line.
+fn tempo_map(options: &RenderOptions, entry: &AppEntry) -> Result<Option<TempoMap>> {
+    match &options.tempo_map {
+        Some(path) => Ok(Some(TempoMap::load(path)?)),
+        None => (entry.tempo)(),
+    }
+}
+
+/// The last frame sent to the GPU, which the next update mustn't write into buffers under.
+type InFlight = Option<Arc<FenceSignalFuture<Box<dyn GpuFuture>>>>;
+
+/// Updates and draws a frame into `framebuffer`, whose image is `frame`, then takes it to every
+/// target. Waits for the frame in `in_flight` to finish first, so frames don't render alongside
+/// each other; returns once this one is submitted, leaving it in `in_flight`.
+fn render_frame(
+    app: &mut dyn DynApp,
+    context: &mut FrameContext,
+    queue: &Arc<Queue>,
+    framebuffer: Arc<Framebuffer>,
+    frame: Arc<Image>,
+    targets: &mut [&mut dyn RenderTarget],
+    in_flight: &mut InFlight,
+) -> Result<()> {
+    let mut before_render = sync::now(queue.device().clone()).boxed();
+    for target in targets.iter_mut() {
+        if let Some(ready) = target.prepare()? {
+            before_render = before_render.join(ready).boxed();
+        }
+    }
+
+    // the app writes straight into the buffers the last frame reads from, so it has to be done
+    // rendering before the update. only the targets' work above runs alongside it; the update,
+    // drawing and submission below don't
+    if let Some(previous) = in_flight.take() {
+        previous.wait(None)?;
+    }
+
+    // update and send data to buffers
+    let command_buffer_allocator = context.command_buffer_allocator.clone();
+    let mut upload_command_buffer = AutoCommandBufferBuilder::primary(
+        command_buffer_allocator.as_ref(),
+        queue.queue_family_index(),
+        CommandBufferUsage::OneTimeSubmit,
+    )?;
+    app.update(context, &mut upload_command_buffer)?;
+
+    // render everything, then send it wherever it's going
+    let mut render_command_buffer = begin_render_command_buffer(
+        &command_buffer_allocator,
+        queue,
+        framebuffer,
+        [0.0, 0.0, 0.0, 1.0],
+    )?;
+    app.draw(context, &mut render_command_buffer)?;
+    render_command_buffer.end_render_pass(SubpassEndInfo::default())?;
+    for target in targets.iter_mut() {
+        target.record(&mut render_command_buffer, frame.clone())?;
+    }
+
+    // vulkano only shares futures through an `Arc`, even on one thread
+    #[allow(clippy::arc_with_non_send_sync)]
+    let rendered = Arc::new(
+        before_render
+            .then_execute(queue.clone(), upload_command_buffer.build()?)?
+            .then_execute(queue.clone(), render_command_buffer.build()?)?
+            .boxed()
+            .then_signal_fence_and_flush()?,
+    );
+    for target in targets.iter_mut() {
+        target.submitted(queue, Box::new(rendered.clone()))?;
+    }
+    *in_flight = Some(rendered);
+    Ok(())
+}
+
+/// Where to start and stop rendering, in seconds into the piece.
+fn render_range(
+    options: &RenderOptions,
+    entry: &AppEntry,
+    tempo: Option<&TempoMap>,
+) -> Result<(f64, Option<f64>)> {
+    let start = options.from.unwrap_or(entry.start).seconds(tempo)?;
+    let end = match options.to {
+        Some(to) => Some(to.seconds(tempo)?),
+        // starting past where the app would stop renders the rest of it
+        None => entry
+            .end
+            .map(|end| end.seconds(tempo))
+            .transpose()?
+            .filter(|&end| end > start),
+    };
+    if end.is_some_and(|end| end <= start) {
+        bail!("nothing to render; `--to` is before `--from`");
+    }
+    Ok((start, end))
 }
 
-type TargetApp = TA1LSD003;
 fn main() -> Result<()> {
+    match cli::parse(std::env::args().skip(1))? {
+        Command::Render(options) => {
+            let entry = match &options.project {
+                Some(project) => {
+                    if let Some(charset) = project.charset() {
+                        termbuf::set_charset(charset);
+                    }
+                    project.entry()?
+                }
+                None => anim::find(&options.app).ok_or(anyhow!(
+                    "no app called `{}`; see `list` for every app",
+                    options.app
+                ))?,
+            };
+            if options.headless {
+                run_headless(&options, &entry)
+            } else {
+                run_windowed(&options, &entry)
+            }
+        }
+        Command::List => {
+            for entry in anim::registry() {
+                println!(
+                    "{} ({}x{})",
+                    entry.name, entry.initial_size.width, entry.initial_size.height
+                );
+            }
+            Ok(())
+        }
+        Command::Help => {
+            print!("{}", cli::USAGE);
+            Ok(())
+        }
+    }
+}
+
+/// Renders the whole piece without ever touching a window system, for machines with no display.
+fn run_headless(options: &RenderOptions, entry: &AppEntry) -> Result<()> {
+    let size = options.size.unwrap_or(entry.initial_size);
+    let tempo = tempo_map(options, entry)?;
+    let (start, end) = render_range(options, entry, tempo.as_ref())?;
+    let mut clock = FixedStep::new(start, options.frame_rate);
+
+    // initialise vulkan, without any of the surface extensions
+    let library = VulkanLibrary::new()?;
+    let instance = Instance::new(
+        library,
+        InstanceCreateInfo {
+            flags: InstanceCreateFlags::ENUMERATE_PORTABILITY,
+            ..Default::default()
+        },
+    )?;
+
+    // get device and queue
+    let required_device_extensions = DeviceExtensions::empty();
+    let (physical_device, queue_family_index) =
+        get_physical_device(&instance, None, &required_device_extensions);
+    let samples = clamp_sample_count(&physical_device, options.msaa);
+    let (device, queue) = create_device(
+        physical_device,
+        queue_family_index,
+        required_device_extensions,
+    )?;
+
+    // buffer/image allocator
+    let allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+
+    // create render pass
+    let recording_format = Format::R32G32B32A32_SFLOAT;
+    let extent = [size.width, size.height, 1];
+    let render_pass = create_render_pass(device.clone(), recording_format, samples)?;
+
+    let command_buffer_allocator = Arc::new(StandardCommandBufferAllocator::new(
+        device.clone(),
+        StandardCommandBufferAllocatorCreateInfo::default(),
+    ));
+    let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(
+        device.clone(),
+        Default::default(),
+    ));
+
+    let mut recorder = Recorder::new(
+        allocator.clone(),
+        descriptor_set_allocator.clone(),
+        &render_pass,
+        recording_format,
+        extent,
+        options.supersample,
+        options.readback_depth,
+    )?;
+    recorder.start(create_sinks(options, extent)?)?;
+
+    let [render_width, render_height, _] = recorder.render_extent();
+    let viewport = Viewport {
+        offset: [0.0, 0.0],
+        extent: [render_width as f32, render_height as f32],
+        depth_range: 0.0..=1.0,
+    };
+
+    let mut app = create_app(
+        entry,
+        &command_buffer_allocator,
+        &queue,
+        allocator.clone(),
+        render_pass.clone(),
+        viewport,
+    )?;
+    let mut context = FrameContext::new(
+        device.clone(),
+        allocator.clone(),
+        command_buffer_allocator.clone(),
+        descriptor_set_allocator,
+        [render_width, render_height],
+        options.frame_rate,
+        tempo,
+    );
+    let audio = entry.audio.clone().or(app.audio().map(String::from));
+
+    let mut in_flight = None;
+    loop {
+        context.advance(clock.time());
+        let framebuffer = recorder.framebuffer()?;
+        let frame = recorder.image();
+        render_frame(
+            app.as_mut(),
+            &mut context,
+            &queue,
+            framebuffer,
+            frame,
+            &mut [&mut recorder],
+            &mut in_flight,
+        )?;
+
+        clock.advance();
+        if app.done() || end.is_some_and(|end| clock.time() >= end) {
+            break;
+        }
+    }
+
+    // the audio starts wherever the render did
+    recorder.finish(audio.as_deref().map(|audio| (audio, start)))
+}
+
+fn run_windowed(options: &RenderOptions, entry: &AppEntry) -> Result<()> {
     let event_loop = EventLoop::new();
 
     // let size = PhysicalSize::new(9 * 128, 16 * 48);
-    let size = TargetApp::INITIAL_SIZE;
-
+    let size = options.size.unwrap_or(entry.initial_size);
+    let tempo = tempo_map(options, entry)?;
+    let (start, end) = render_range(options, entry, tempo.as_ref())?;
+    let frame_rate = options.frame_rate;
+    // the closure below outlives this function
+    let options = options.clone();
+
+    // the window can be resized freely; frames are letterboxed into it
     let window = Arc::new(
         WindowBuilder::new()
-            // winit likes to change window sizes if we dont do this
-            .with_max_inner_size(size)
-            .with_min_inner_size(size)
-            .with_inner_size(size)
+            .with_inner_size(options.window_size.unwrap_or(size))
             .build(&event_loop)?,
     );
 
@@ -361,299 +662,186 @@ fn main() -> Result<()> {
         ..DeviceExtensions::empty()
     };
     let (physical_device, queue_family_index) =
-        get_physical_device(&instance, &surface, &required_device_extensions);
-    let (device, mut queues) = Device::new(
+        get_physical_device(&instance, Some(&surface), &required_device_extensions);
+    let samples = clamp_sample_count(&physical_device, options.msaa);
+    let (device, queue) = create_device(
         physical_device.clone(),
-        DeviceCreateInfo {
-            queue_create_infos: vec![QueueCreateInfo {
-                queue_family_index,
-                ..Default::default()
-            }],
-            enabled_extensions: required_device_extensions,
-            ..Default::default()
-        },
+        queue_family_index,
+        required_device_extensions,
     )?;
-    let queue = queues.next().unwrap();
 
-    let surface_capabilities = physical_device
-        .clone()
-        .surface_capabilities(&surface, Default::default())?;
-
-    let dimensions = window.inner_size();
-    let composite_alpha = surface_capabilities
-        .supported_composite_alpha
-        .into_iter()
-        .next()
-        .unwrap();
-    let image_format = physical_device.surface_formats(&surface, Default::default())?[0].0;
-    let (mut swapchain, images) = Swapchain::new(
-        device.clone(),
-        surface.clone(),
-        SwapchainCreateInfo {
-            min_image_count: surface_capabilities.min_image_count + 1,
-            image_format,
-            present_mode: PresentMode::Immediate,
-            image_extent: dimensions.into(),
-            image_usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC,
-            composite_alpha,
-            ..Default::default()
-        },
-    )?;
+    // previews run at the display's rate, renders as fast as they can
+    let present_mode = if options.preview {
+        PresentMode::Fifo
+    } else {
+        PresentMode::Immediate
+    };
+    let mut window_target =
+        WindowTarget::new(device.clone(), surface, window.clone(), present_mode)?;
 
     // buffer/image allocator
     let allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
 
     // create render pass
+    // frames are always rendered at the output size (or a multiple of it when supersampling),
+    // however big the window ends up being
     let recording_format = Format::R32G32B32A32_SFLOAT;
-    let extent = images[0].extent();
-    let render_pass = create_render_pass(device.clone(), recording_format)?;
+    let extent = [size.width, size.height, 1];
+    let render_pass = create_render_pass(device.clone(), recording_format, samples)?;
 
-    // create image for recorded output
-    let recording_image = Image::new(
-        allocator.clone(),
-        ImageCreateInfo {
-            format: recording_format,
-            image_type: ImageType::Dim2d,
-            usage: ImageUsage::TRANSFER_DST
-                | ImageUsage::TRANSFER_SRC
-                | ImageUsage::COLOR_ATTACHMENT,
-            extent,
-            ..Default::default()
-        },
-        AllocationCreateInfo::default(),
-    )?;
-    // create framebuffer for recording image
-    let recording_framebuffer =
-        create_framebuffers(&[recording_image.clone()], &render_pass, allocator.clone())?.remove(0);
-    // create staging buffer for saving image
-    let pixel_count = extent[0] as usize * extent[1] as usize * 4 as usize;
-    let recording_staging_buffer = Buffer::from_iter(
+    // create command buffer and descriptor set allocators
+    let command_buffer_allocator = Arc::new(StandardCommandBufferAllocator::new(
+        device.clone(),
+        StandardCommandBufferAllocatorCreateInfo::default(),
+    ));
+    let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(
+        device.clone(),
+        Default::default(),
+    ));
+
+    let mut recorder = Recorder::new(
         allocator.clone(),
-        BufferCreateInfo {
-            usage: BufferUsage::TRANSFER_DST,
-            ..Default::default()
-        },
-        AllocationCreateInfo {
-            memory_type_filter: MemoryTypeFilter::HOST_RANDOM_ACCESS,
-            ..Default::default()
-        },
-        (0..pixel_count).map(|_| 0.0f32),
+        descriptor_set_allocator.clone(),
+        &render_pass,
+        recording_format,
+        extent,
+        options.supersample,
+        options.readback_depth,
     )?;
-    // we cant render with unorm, but we also cant save with sfloat, so a conversion is required
-    let mut unorm_buffer = vec![0u8; pixel_count];
 
-    // create framebuffers for swapchain
-    // let mut framebuffers = create_framebuffers(&images, &render_pass, allocator.clone())?;
-
-    // create graphics pipelines
-    let mut viewport = Viewport {
+    let [render_width, render_height, _] = recorder.render_extent();
+    let viewport = Viewport {
         offset: [0.0, 0.0],
-        extent: window.inner_size().into(),
+        extent: [render_width as f32, render_height as f32],
         depth_range: 0.0..=1.0,
     };
 
-    // create command buffer allocator and terminal panel
-    let command_buffer_allocator = StandardCommandBufferAllocator::new(
-        device.clone(),
-        StandardCommandBufferAllocatorCreateInfo::default(),
-    );
-    let mut loader_command_buffer = AutoCommandBufferBuilder::primary(
-        &command_buffer_allocator,
-        queue_family_index,
-        CommandBufferUsage::OneTimeSubmit,
-    )?;
-
-    let mut force_recreate_swapchain = false;
-    let mut window_resized = false;
-
-    let in_flight_frame_count = images.len();
-    let mut image_fences: Vec<Option<Arc<FenceSignalFuture<_>>>> =
-        vec![None; in_flight_frame_count];
-    let mut previous_fence_index = 0;
-
     // let mut last_render_time = Instant::now();
 
-    let mut app = TargetApp::new(
-        &mut loader_command_buffer,
+    let mut app = create_app(
+        entry,
+        &command_buffer_allocator,
+        &queue,
         allocator.clone(),
-        device.clone(),
         render_pass.clone(),
-        viewport.clone(),
+        viewport,
     )?;
-
-    drop(loader_command_buffer.build()?.execute(queue.clone())?);
-
-    let mut frame_index = 0;
-    let (mut ffmpeg, pixel_input) =
-        ffmpeg_video_stream(extent[0] as usize, extent[1] as usize, "output.mp4").unwrap();
-    let mut pixel_input = Some(pixel_input);
+    let mut context = FrameContext::new(
+        device.clone(),
+        allocator.clone(),
+        command_buffer_allocator.clone(),
+        descriptor_set_allocator,
+        [render_width, render_height],
+        options.frame_rate,
+        tempo,
+    );
+    let audio = entry.audio.clone().or(app.audio().map(String::from));
+
+    // a preview plays in real time, and only records while asked to. otherwise everything is
+    // recorded
+    let mut transport = if options.preview {
+        println!(
+            "previewing; press R to start and stop recording, space to pause and the arrow keys \
+             to skip around"
+        );
+        Transport::preview(audio.as_deref(), start, frame_rate)?
+    } else {
+        recorder.start(create_sinks(&options, extent)?)?;
+        Transport::recording(start, frame_rate)
+    };
+    let mut held_keys = HashSet::new();
+    let mut recorder = Some(recorder);
+    let mut in_flight = None;
 
     event_loop.run(move |event, _, control_flow| match event {
         Event::WindowEvent {
             event: WindowEvent::CloseRequested,
             ..
         } => {
+            // keep whatever has been recorded so far
+            if let (Some(mut recording), Some(take_start)) =
+                (recorder.take(), transport.take_start())
+            {
+                recording
+                    .stop(audio.as_deref().map(|audio| (audio, take_start)))
+                    .unwrap();
+            }
             *control_flow = ControlFlow::Exit;
         }
         Event::WindowEvent {
-            event: WindowEvent::Resized(_),
+            event:
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state,
+                            virtual_keycode: Some(key),
+                            ..
+                        },
+                    ..
+                },
             ..
-        } => {
-            window_resized = true;
-        }
-        Event::MainEventsCleared => {
-            if force_recreate_swapchain || window_resized {
-                force_recreate_swapchain = false;
-
-                let new_size = window.inner_size();
-
-                let (new_swapchain, new_images) = swapchain
-                    .recreate(SwapchainCreateInfo {
-                        image_extent: new_size.into(),
-                        ..swapchain.create_info()
-                    })
-                    .unwrap();
-
-                swapchain = new_swapchain;
-                // framebuffers =
-                //     create_framebuffers(&new_images, &render_pass, allocator.clone()).unwrap();
-
-                if window_resized {
-                    window_resized = false;
-
-                    viewport.extent = new_size.into();
-
-                    app.resize(new_size).unwrap();
+        } if options.preview => {
+            // holding a key down repeats the press, which only scrubbing wants
+            let repeated = match state {
+                ElementState::Pressed => !held_keys.insert(key),
+                ElementState::Released => {
+                    held_keys.remove(&key);
+                    return;
                 }
-            }
-
-            // update and send data to buffers
-            let mut upload_command_buffer = AutoCommandBufferBuilder::primary(
-                &command_buffer_allocator,
-                queue_family_index,
-                CommandBufferUsage::OneTimeSubmit,
-            )
-            .unwrap();
+            };
+            let Some(recording) = recorder.as_mut() else {
+                return;
+            };
 
-            app.update(&mut upload_command_buffer).unwrap();
-
-            let upload_commands = upload_command_buffer.build().unwrap();
-            sync::now(device.clone())
-                .then_execute(queue.clone(), upload_commands)
-                .unwrap()
-                .then_signal_fence_and_flush()
-                .unwrap()
-                .wait(None)
-                .unwrap();
-
-            // acquire next swapchain image
-            let (image_index, is_suboptimal, acquire_future) =
-                match vulkano::swapchain::acquire_next_image(swapchain.clone(), None)
-                    .map_err(Validated::unwrap)
-                {
-                    Result::Ok(good) => good,
-                    Result::Err(VulkanError::OutOfDate) => {
-                        force_recreate_swapchain = true;
-                        return;
+            match key {
+                VirtualKeyCode::R if !repeated => match transport.take_start() {
+                    Some(take_start) => {
+                        recording
+                            .stop(audio.as_deref().map(|audio| (audio, take_start)))
+                            .unwrap();
+                        transport.stop_take().unwrap();
+                        println!("stopped recording");
                     }
-                    Result::Err(e) => {
-                        panic!("vulkan error while acquiring next swapchain image: {e}")
+                    None => {
+                        recording
+                            .start(create_sinks(&options, extent).unwrap())
+                            .unwrap();
+                        let take_start = transport.start_take();
+                        println!("recording from {take_start:.2}s");
+                    }
+                },
+                VirtualKeyCode::Space if !repeated => {
+                    transport.toggle_pause().unwrap();
+                    if transport.is_paused() {
+                        println!("paused at {:.2}s", transport.clock().time());
                     }
-                };
-
-            // render everything
-            let mut render_command_buffer = begin_render_command_buffer(
-                &command_buffer_allocator,
-                &queue,
-                recording_framebuffer.clone(),
-                // framebuffers[image_index as usize].clone(),
-                [0.0, 0.0, 0.0, 1.0],
-            )
-            .unwrap();
-
-            app.draw(&mut render_command_buffer).unwrap();
-
-            render_command_buffer
-                .end_render_pass(SubpassEndInfo::default())
-                .unwrap();
-
-            let render_commands = render_command_buffer.build().unwrap();
-
-            // submit image
-            if is_suboptimal {
-                force_recreate_swapchain = true;
-            }
-
-            if let Some(image_fence) = &image_fences[image_index as usize] {
-                image_fence.wait(None).unwrap();
-            }
-
-            let previous_fence = match image_fences[previous_fence_index as usize].clone() {
-                None => {
-                    let mut nothing = sync::now(device.clone());
-                    nothing.cleanup_finished();
-                    nothing.boxed()
-                }
-                Some(fence) => fence.boxed(),
-            };
-
-            let execution_result = sync::now(device.clone())
-                .join(acquire_future)
-                .join(previous_fence)
-                .then_execute(queue.clone(), render_commands)
-                .unwrap()
-                .then_swapchain_present(
-                    queue.clone(),
-                    SwapchainPresentInfo::swapchain_image_index(swapchain.clone(), image_index),
-                )
-                .then_signal_fence_and_flush();
-
-            image_fences[image_index as usize] = match execution_result.map_err(Validated::unwrap) {
-                Result::Ok(future) => Some(Arc::new(future)),
-                Result::Err(VulkanError::OutOfDate) => {
-                    force_recreate_swapchain = true;
-                    None
                 }
-                Result::Err(err) => panic!("error while rendering: {err}"),
+                VirtualKeyCode::Left => transport.scrub(-1.0).unwrap(),
+                VirtualKeyCode::Right => transport.scrub(1.0).unwrap(),
+                _ => {}
+            }
+        }
+        Event::MainEventsCleared => {
+            let Some(recording) = recorder.as_mut() else {
+                return;
             };
-
-            let mut copy_buffer = AutoCommandBufferBuilder::primary(
-                &command_buffer_allocator,
-                queue_family_index,
-                CommandBufferUsage::OneTimeSubmit,
+            context.advance(transport.clock().time());
+
+            // the window shows what's being recorded, and frames are still rendered and recorded
+            // while there's no window to show them on
+            let framebuffer = recording.framebuffer().unwrap();
+            let frame = recording.image();
+            render_frame(
+                app.as_mut(),
+                &mut context,
+                &queue,
+                framebuffer,
+                frame,
+                &mut [&mut window_target, recording],
+                &mut in_flight,
             )
             .unwrap();
-            copy_buffer
-                .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
-                    recording_image.clone(),
-                    recording_staging_buffer.clone(),
-                ))
-                .unwrap();
-            let copy_commands = copy_buffer.build().unwrap();
-            sync::now(device.clone())
-                .then_execute(queue.clone(), copy_commands)
-                .unwrap()
-                .then_signal_fence_and_flush()
-                .unwrap()
-                .wait(None)
-                .unwrap();
-
-            for (dst, src) in unorm_buffer
-                .iter_mut()
-                .zip(recording_staging_buffer.read().unwrap().iter())
-            {
-                *dst = (src * 255.0) as u8;
-            }
 
-            pixel_input.as_mut().unwrap().write(&unorm_buffer).unwrap();
-            println!("copied {frame_index}");
-
-            // let mut output = RgbaImage::new(extent[0], extent[1]);
-            // output.copy_from_slice(&unorm_buffer);
-            // output.save(format!("output_{frame_index:06}.png")).unwrap();
-
-            previous_fence_index = image_index;
-            frame_index += 1;
             // let current_time = Instant::now();
             // println!(
             //     "{:?} FPS",
@@ -661,11 +849,16 @@ fn main() -> Result<()> {
             // );
             // last_render_time = current_time;
 
-            if app.done() {
-                drop(pixel_input.take());
-                ffmpeg.wait().unwrap();
-                let (audio_file, audio_offset) = app.audio().unwrap();
-                merge_av(audio_file, audio_offset, "output.mp4", "done.mp4").unwrap();
+            let clock = transport.clock();
+            clock.advance();
+            if app.done() || end.is_some_and(|end| clock.time() >= end) {
+                let mut recording = recorder.take().unwrap();
+                if let Some(take_start) = transport.take_start() {
+                    // the audio starts wherever the recording did
+                    recording
+                        .stop(audio.as_deref().map(|audio| (audio, take_start)))
+                        .unwrap();
+                }
                 control_flow.set_exit();
             }
         }
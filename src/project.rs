@@ -0,0 +1,262 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+
+use crate::{
+    anim,
+    cli::parse_size,
+    renderer::{
+        app::AppEntry,
+        tempo::{TempoMap, TimePoint},
+        timeline::{Timeline, Transition},
+    },
+};
+
+/// A piece set up in a file instead of in code, by putting apps that already exist on a timeline.
+/// Looks like:
+///
+/// ```toml
+/// audio = "ta1lsd003.mp3"
+/// charset = "charset.png"
+/// size = "1152x1024"
+/// fps = 60   # or 29.97, or "30000/1001"
+///
+/// [tempo]
+/// bpm = 150
+/// offset = 0.0134
+/// # or a tempo map instead; see `TempoMap::parse`
+/// # map = "ta1lsd003.tempo"
+///
+/// # anything `render` takes, by the name of its flag. flags given on the command line replace
+/// # these, lists included, but `headless = true` and `preview = true` can't be turned off there
+/// [render]
+/// profile = "final"
+/// supersample = 2
+/// sink = ["video", "png"]
+///
+/// [[scene]]
+/// app = "ta1lsd003"
+/// beats = [0, 224]
+///
+/// [[scene]]
+/// app = "ta1lsd005"
+/// beats = [224, 320]
+/// transition = { crossfade = 4 }   # or "cut" (the default), or { wipe = 1 }
+/// ```
+///
+/// Files named in the project are found relative to it, apart from the render settings, which
+/// work exactly as they do on the command line.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Project {
+    #[serde(skip)]
+    name: String,
+    /// Where the project file is.
+    #[serde(skip)]
+    dir: PathBuf,
+    audio: Option<String>,
+    charset: Option<String>,
+    size: Option<String>,
+    fps: Option<toml::Value>,
+    tempo: Option<ProjectTempo>,
+    #[serde(default)]
+    render: BTreeMap<String, toml::Value>,
+    #[serde(default, rename = "scene")]
+    scenes: Vec<Scene>,
+}
+
+/// Either a constant tempo or a tempo map, never both.
+#[derive(Clone, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum ProjectTempo {
+    Constant {
+        bpm: f64,
+        #[serde(default)]
+        offset: f64,
+    },
+    Map {
+        map: String,
+    },
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Scene {
+    app: String,
+    beats: [f64; 2],
+    #[serde(default = "cut")]
+    transition: Transition,
+}
+fn cut() -> Transition {
+    Transition::Cut
+}
+
+impl Project {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("couldn't read project `{}`", path.display()))?;
+        let mut project: Project =
+            toml::from_str(&text).with_context(|| format!("in project `{}`", path.display()))?;
+        project.name = path
+            .file_stem()
+            .map_or("project".into(), |stem| stem.to_string_lossy().into());
+        project.dir = path.parent().unwrap_or(Path::new("")).into();
+        Ok(project)
+    }
+
+    /// The project's render settings, as the flags they stand for. They go before the ones
+    /// actually `given`, so those override them, and settings whose flag is given at all are left
+    /// out so that repeatable flags like `--sink` start over rather than adding to the project's.
+    pub fn args(&self, given: &[String]) -> Result<Vec<String>> {
+        let given = |flag: &str| given.iter().any(|arg| arg == flag);
+        let mut args = Vec::new();
+        if let Some(fps) = self.fps.as_ref().filter(|_| !given("--fps")) {
+            let fps = match fps {
+                toml::Value::String(fps) => fps.clone(),
+                toml::Value::Integer(_) | toml::Value::Float(_) => fps.to_string(),
+                fps => bail!("fps can't be a {} in a project", fps.type_str()),
+            };
+            args.extend(["--fps".into(), fps]);
+        }
+        for (key, value) in &self.render {
+            let flag = format!("--{key}");
+            if given(&flag) {
+                continue;
+            }
+            // a list gives the same flag several times
+            let values = match value {
+                toml::Value::Array(values) => values.clone(),
+                value => vec![value.clone()],
+            };
+            for value in values {
+                match value {
+                    toml::Value::Boolean(true) => args.push(flag.clone()),
+                    toml::Value::Boolean(false) => {}
+                    toml::Value::String(value) => args.extend([flag.clone(), value]),
+                    toml::Value::Integer(_) | toml::Value::Float(_) => {
+                        args.extend([flag.clone(), value.to_string()])
+                    }
+                    value => bail!(
+                        "render setting `{key}` can't be a {} in a project",
+                        value.type_str()
+                    ),
+                }
+            }
+        }
+        Ok(args)
+    }
+
+    /// Where the characters of terminal panels come from, if the project says.
+    pub fn charset(&self) -> Option<String> {
+        self.charset
+            .as_ref()
+            .map(|charset| self.dir.join(charset).to_string_lossy().into())
+    }
+
+    /// The project as something to render, with its scenes on a [`Timeline`].
+    pub fn entry(&self) -> Result<AppEntry> {
+        let scenes = self
+            .scenes
+            .iter()
+            .map(|scene| {
+                let entry = anim::find(&scene.app)
+                    .ok_or(anyhow!("no app called `{}` for a scene to show", scene.app))?;
+                let [start, end] = scene.beats;
+                Ok((entry, start..end, scene.transition))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let Some((first, ..)) = scenes.first() else {
+            bail!("project `{}` has no scenes", self.name);
+        };
+
+        let initial_size = match &self.size {
+            Some(size) => parse_size(size)?,
+            None => first.initial_size,
+        };
+        // loaded now, so a broken tempo map shows up before anything gets set up
+        let tempo = match &self.tempo {
+            Some(ProjectTempo::Constant { bpm, offset }) => Some(TempoMap::new(*bpm, *offset)),
+            Some(ProjectTempo::Map { map }) => Some(TempoMap::load(self.dir.join(map))?),
+            None => (first.tempo)()?,
+        };
+
+        Ok(AppEntry {
+            name: self.name.clone(),
+            initial_size,
+            start: TimePoint::Seconds(0.0),
+            end: None,
+            tempo: Box::new(move || Ok(tempo.clone())),
+            audio: self
+                .audio
+                .as_ref()
+                .map(|audio| self.dir.join(audio).to_string_lossy().into()),
+            new: Box::new(
+                move |loader_command_buffer, allocator, device, render_pass, viewport| {
+                    let mut timeline = Timeline::new(allocator, device, render_pass, viewport)?;
+                    for (entry, beats, transition) in &scenes {
+                        timeline = timeline.entry(
+                            loader_command_buffer,
+                            entry,
+                            beats.clone(),
+                            *transition,
+                        )?;
+                    }
+                    Ok(Box::new(timeline))
+                },
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(project: &str, given: &[&str]) -> Vec<String> {
+        let project: Project = toml::from_str(project).unwrap();
+        let given: Vec<String> = given.iter().map(|arg| arg.to_string()).collect();
+        project.args(&given).unwrap()
+    }
+
+    #[test]
+    fn lists_repeat_their_flag() {
+        let project = "[render]\nsink = [\"video\", \"png\"]";
+        assert_eq!(args(project, &[]), ["--sink", "video", "--sink", "png"]);
+    }
+
+    #[test]
+    fn booleans_are_switches() {
+        let project = "[render]\nheadless = true\npreview = false";
+        assert_eq!(args(project, &[]), ["--headless"]);
+    }
+
+    #[test]
+    fn numeric_fps() {
+        assert_eq!(args("fps = 60", &[]), ["--fps", "60"]);
+        assert_eq!(args("fps = 29.97", &[]), ["--fps", "29.97"]);
+        assert_eq!(args("fps = \"30000/1001\"", &[]), ["--fps", "30000/1001"]);
+    }
+
+    #[test]
+    fn given_flags_replace_settings() {
+        let project = "fps = 60\n[render]\nprofile = \"final\"\nsink = [\"video\", \"png\"]";
+        assert_eq!(
+            args(project, &["--sink", "y4m", "--fps", "30"]),
+            ["--profile", "final"]
+        );
+    }
+
+    #[test]
+    fn tempo_is_bpm_or_map() {
+        let tempo = |text: &str| toml::from_str::<Project>(&format!("[tempo]\n{text}"));
+        assert!(tempo("bpm = 150\noffset = 0.5").is_ok());
+        assert!(tempo("map = \"piece.tempo\"").is_ok());
+        assert!(tempo("bpm = 150\nmap = \"piece.tempo\"").is_err());
+    }
+}
@@ -1,8 +1,14 @@
 use std::sync::Arc;
 
 use anyhow::Result;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
 use vulkano::{
-    command_buffer::{allocator::CommandBufferAllocator, AutoCommandBufferBuilder},
+    command_buffer::{
+        allocator::{CommandBufferAllocator, StandardCommandBufferAllocator},
+        AutoCommandBufferBuilder, PrimaryAutoCommandBuffer,
+    },
+    descriptor_set::allocator::StandardDescriptorSetAllocator,
     device::Device,
     memory::allocator::MemoryAllocator,
     pipeline::graphics::viewport::Viewport,
@@ -10,8 +16,100 @@ use vulkano::{
 };
 use winit::dpi::PhysicalSize;
 
+use super::{
+    clock::FrameRate,
+    tempo::{TempoMap, TimePoint},
+};
+
+/// Everything the runtime knows about the frame being made, handed to [`App::update`] and
+/// [`App::draw`].
+pub struct FrameContext {
+    /// Seconds since the start of the piece, which is also the start of the audio. Scenes on a
+    /// [`Timeline`](super::timeline::Timeline) count from their own start instead, as they do for
+    /// beats and bars.
+    pub time: f64,
+    /// Beats since the start of the piece, by its tempo map. Pieces without a tempo count one beat
+    /// a second.
+    pub beat: f64,
+    /// Which bar the beat is in, with how far through it as the fraction.
+    pub bar: f64,
+    /// How many frames came before this one.
+    pub frame: usize,
+    /// Seconds since the last frame, or 0 for the first.
+    pub delta: f64,
+    /// The rate frames are recorded at. Previews play in real time rather than keeping to it, so
+    /// `delta` is how far time actually moved.
+    pub frame_rate: FrameRate,
+    /// The size frames are rendered at, supersampling included.
+    pub resolution: [u32; 2],
+    pub device: Arc<Device>,
+    pub allocator: Arc<dyn MemoryAllocator>,
+    /// For command buffers of an app's own, like one-off uploads.
+    pub command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    /// Lives as long as the runtime, so apps needn't make one for every set.
+    pub descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    /// Seeded from the time, so the same moment always gets the same numbers, however it was
+    /// reached.
+    pub rng: ChaCha8Rng,
+    tempo: TempoMap,
+    started: bool,
+}
+impl FrameContext {
+    pub fn new(
+        device: Arc<Device>,
+        allocator: Arc<dyn MemoryAllocator>,
+        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        resolution: [u32; 2],
+        frame_rate: FrameRate,
+        tempo: Option<TempoMap>,
+    ) -> Self {
+        Self {
+            time: 0.0,
+            beat: 0.0,
+            bar: 0.0,
+            frame: 0,
+            delta: 0.0,
+            frame_rate,
+            resolution,
+            device,
+            allocator,
+            command_buffer_allocator,
+            descriptor_set_allocator,
+            rng: ChaCha8Rng::seed_from_u64(0),
+            tempo: tempo.unwrap_or(TempoMap::new(60.0, 0.0)),
+            started: false,
+        }
+    }
+
+    /// Moves on to the next frame, at `time` seconds into the piece.
+    pub fn advance(&mut self, time: f64) {
+        if self.started {
+            self.frame += 1;
+            self.delta = time - self.time;
+        }
+        self.started = true;
+        self.time = time;
+        self.beat = self.tempo.beat(time);
+        self.bar = self.tempo.bar(self.beat);
+        self.rng = ChaCha8Rng::seed_from_u64(time.to_bits());
+    }
+
+    /// Width over height.
+    pub fn aspect(&self) -> f32 {
+        self.resolution[0] as f32 / self.resolution[1] as f32
+    }
+
+    pub fn tempo(&self) -> &TempoMap {
+        &self.tempo
+    }
+}
+
 pub trait App: Sized {
     const INITIAL_SIZE: PhysicalSize<u32> = PhysicalSize::new(1600, 900);
+    /// Where rendering starts and stops when `--from` and `--to` aren't given.
+    const START: TimePoint = TimePoint::Seconds(0.0);
+    const END: Option<TimePoint> = None;
 
     fn new<L, A: CommandBufferAllocator + 'static>(
         loader_command_buffer: &mut AutoCommandBufferBuilder<L, A>,
@@ -23,19 +121,116 @@ pub trait App: Sized {
 
     fn update<L, A: CommandBufferAllocator + 'static>(
         &mut self,
+        context: &mut FrameContext,
         upload_command_buffer: &mut AutoCommandBufferBuilder<L, A>,
     ) -> Result<()>;
 
+    /// The viewport and scissor are already set to the whole frame, whatever size it is.
     fn draw<L, A: CommandBufferAllocator + 'static>(
         &mut self,
+        context: &mut FrameContext,
         render_command_buffer: &mut AutoCommandBufferBuilder<L, A>,
     ) -> Result<()>;
 
-    fn resize(&mut self, new_size: PhysicalSize<u32>) -> Result<()>;
+    /// Where the beats of the piece fall. Lets the start and end of a render be given in beats.
+    fn tempo() -> Result<Option<TempoMap>> {
+        Ok(None)
+    }
+
     fn done(&self) -> bool {
         false
     }
-    fn audio(&self) -> Option<(&'static str, f64)> {
+    /// The track to go with the piece.
+    fn audio(&self) -> Option<&'static str> {
         None
     }
 }
+
+/// The only command buffer type the runtime ever hands to apps.
+pub type CommandBuilder =
+    AutoCommandBufferBuilder<PrimaryAutoCommandBuffer, StandardCommandBufferAllocator>;
+
+/// Object-safe version of [`App`], with the generic command buffers pinned to [`CommandBuilder`].
+/// Every [`App`] implements this, so apps can be picked at runtime.
+pub trait DynApp {
+    fn update(
+        &mut self,
+        context: &mut FrameContext,
+        upload_command_buffer: &mut CommandBuilder,
+    ) -> Result<()>;
+    fn draw(
+        &mut self,
+        context: &mut FrameContext,
+        render_command_buffer: &mut CommandBuilder,
+    ) -> Result<()>;
+    fn done(&self) -> bool;
+    fn audio(&self) -> Option<&'static str>;
+}
+impl<T: App> DynApp for T {
+    fn update(
+        &mut self,
+        context: &mut FrameContext,
+        upload_command_buffer: &mut CommandBuilder,
+    ) -> Result<()> {
+        App::update(self, context, upload_command_buffer)
+    }
+    fn draw(
+        &mut self,
+        context: &mut FrameContext,
+        render_command_buffer: &mut CommandBuilder,
+    ) -> Result<()> {
+        App::draw(self, context, render_command_buffer)
+    }
+    fn done(&self) -> bool {
+        App::done(self)
+    }
+    fn audio(&self) -> Option<&'static str> {
+        App::audio(self)
+    }
+}
+
+pub type AppConstructor = Box<
+    dyn Fn(
+        &mut CommandBuilder,
+        Arc<dyn MemoryAllocator>,
+        Arc<Device>,
+        Arc<RenderPass>,
+        Viewport,
+    ) -> Result<Box<dyn DynApp>>,
+>;
+
+/// A named app that can be instantiated at runtime, either an [`App`] or something put together
+/// from a [`Project`](crate::project::Project).
+pub struct AppEntry {
+    pub name: String,
+    pub initial_size: PhysicalSize<u32>,
+    pub start: TimePoint,
+    pub end: Option<TimePoint>,
+    pub tempo: Box<dyn Fn() -> Result<Option<TempoMap>>>,
+    /// A track to play instead of the app's own.
+    pub audio: Option<String>,
+    pub new: AppConstructor,
+}
+impl AppEntry {
+    pub fn of<T: App + 'static>(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            initial_size: T::INITIAL_SIZE,
+            start: T::START,
+            end: T::END,
+            tempo: Box::new(T::tempo),
+            audio: None,
+            new: Box::new(
+                |loader_command_buffer, allocator, device, render_pass, viewport| {
+                    Ok(Box::new(T::new(
+                        loader_command_buffer,
+                        allocator,
+                        device,
+                        render_pass,
+                        viewport,
+                    )?))
+                },
+            ),
+        }
+    }
+}
@@ -0,0 +1,421 @@
+use std::{
+    fmt,
+    fs::File,
+    io::BufReader,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Result};
+use rodio::{Decoder, OutputStream, Sink};
+
+use super::misc::SinkExtrapolator;
+
+/// Where in the piece each frame is. Apps are only ever handed the time; which clock it comes from
+/// is up to whatever is running them.
+pub trait Clock {
+    /// Seconds into the piece, for the frame about to be rendered.
+    fn time(&mut self) -> f64;
+    /// Called once a frame has been rendered.
+    fn advance(&mut self) {}
+}
+
+/// Frames per second, kept as a ratio so rates like 29.97 (really 30000/1001) stay exact however
+/// long a render goes on for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameRate {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+impl FrameRate {
+    pub const fn new(numerator: u32, denominator: u32) -> Self {
+        Self {
+            numerator,
+            denominator,
+        }
+    }
+
+    /// Parses `60`, `29.97` or `30000/1001`. Decimals that are within rounding of an NTSC
+    /// `x/1.001` rate are taken to mean it.
+    pub fn parse(text: &str) -> Result<Self> {
+        let invalid = || anyhow!("invalid frame rate `{text}`");
+        let (numerator, denominator) = match text.split_once('/') {
+            Some((numerator, denominator)) => (
+                numerator.trim().parse().map_err(|_| invalid())?,
+                denominator.trim().parse().map_err(|_| invalid())?,
+            ),
+            None => {
+                let fps: f64 = text.trim().parse().map_err(|_| invalid())?;
+                if !(fps.is_finite() && fps > 0.0) {
+                    return Err(invalid());
+                }
+                let ntsc = fps * 1.001;
+                if (ntsc - ntsc.round()).abs() < 1e-3 && (fps - fps.round()).abs() > 1e-3 {
+                    (ntsc.round() as u32 * 1000, 1001)
+                } else if (fps - fps.round()).abs() < 1e-6 {
+                    (fps.round() as u32, 1)
+                } else {
+                    ((fps * 1000.0).round() as u32, 1000)
+                }
+            }
+        };
+        if numerator == 0 || denominator == 0 {
+            return Err(invalid());
+        }
+        let divisor = gcd(numerator, denominator);
+        Ok(Self::new(numerator / divisor, denominator / divisor))
+    }
+
+    pub fn as_f64(self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
+    }
+
+    /// How long each frame lasts, in seconds.
+    pub fn frame_duration(self) -> f64 {
+        self.denominator as f64 / self.numerator as f64
+    }
+
+    /// How far in `frame` starts, in seconds. Worked out from scratch every time rather than
+    /// added up, so it doesn't drift.
+    pub fn frame_time(self, frame: u64) -> f64 {
+        (frame as u128 * self.denominator as u128) as f64 / self.numerator as f64
+    }
+}
+impl Default for FrameRate {
+    fn default() -> Self {
+        Self::new(60, 1)
+    }
+}
+/// As ffmpeg takes it: `60`, or `30000/1001`.
+impl fmt::Display for FrameRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.denominator {
+            1 => write!(f, "{}", self.numerator),
+            _ => write!(f, "{}/{}", self.numerator, self.denominator),
+        }
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
+/// Steps through time exactly one frame at a time, however long frames take to render. Used
+/// whenever frames are recorded.
+pub struct FixedStep {
+    start: f64,
+    frame_rate: FrameRate,
+    frame: u64,
+}
+impl FixedStep {
+    pub fn new(start: f64, frame_rate: FrameRate) -> Self {
+        Self {
+            start,
+            frame_rate,
+            frame: 0,
+        }
+    }
+}
+impl Clock for FixedStep {
+    fn time(&mut self) -> f64 {
+        self.start + self.frame_rate.frame_time(self.frame)
+    }
+    fn advance(&mut self) {
+        self.frame += 1;
+    }
+}
+
+/// Time that only moves when told to, for holding still on a frame or scrubbing around.
+pub struct ManualClock {
+    time: f64,
+}
+impl ManualClock {
+    pub fn new(time: f64) -> Self {
+        Self { time }
+    }
+
+    pub fn set(&mut self, time: f64) {
+        self.time = time;
+    }
+}
+impl Clock for ManualClock {
+    fn time(&mut self) -> f64 {
+        self.time
+    }
+}
+
+/// Real time playback of a piece, following its track if it has one, or the wall clock if not.
+pub struct PlaybackClock {
+    // has to outlive the sink, or nothing is heard
+    _stream: Option<OutputStream>,
+    audio: Option<SinkExtrapolator>,
+    /// When playback was (re)started, and where in the piece it was at the time.
+    started: Instant,
+    start: f64,
+    paused: bool,
+}
+impl PlaybackClock {
+    /// Starts playing `audio`, from `start` seconds in.
+    pub fn new(audio: Option<&str>, start: f64) -> Result<Self> {
+        let mut playback = Self {
+            _stream: None,
+            audio: None,
+            started: Instant::now(),
+            start,
+            paused: false,
+        };
+        if let Some(audio) = audio {
+            let (stream, stream_handle) = OutputStream::try_default()?;
+            let sink = Sink::try_new(&stream_handle)?;
+            sink.append(Decoder::new(BufReader::new(
+                File::open(audio).with_context(|| format!("couldn't open `{audio}`"))?,
+            ))?);
+            playback._stream = Some(stream);
+            playback.audio = Some(SinkExtrapolator::new(sink));
+            playback.seek(start)?;
+        }
+        Ok(playback)
+    }
+
+    pub fn pause(&mut self) {
+        self.start = self.time();
+        self.paused = true;
+        if let Some(audio) = &self.audio {
+            audio.sink.pause();
+        }
+    }
+
+    /// Carries on playing from `time` seconds into the piece.
+    pub fn resume(&mut self, time: f64) -> Result<()> {
+        self.seek(time)?;
+        self.paused = false;
+        if let Some(audio) = &self.audio {
+            audio.sink.play();
+        }
+        Ok(())
+    }
+
+    fn seek(&mut self, time: f64) -> Result<()> {
+        self.start = time;
+        self.started = Instant::now();
+        if let Some(audio) = self.audio.take() {
+            audio
+                .sink
+                .try_seek(Duration::from_secs_f64(time.max(0.0)))
+                .map_err(|error| anyhow!("couldn't seek the audio: {error}"))?;
+            // the old extrapolation is meaningless after a jump
+            self.audio = Some(SinkExtrapolator::new(audio.sink));
+        }
+        Ok(())
+    }
+}
+impl Clock for PlaybackClock {
+    fn time(&mut self) -> f64 {
+        match (&mut self.audio, self.paused) {
+            (_, true) => self.start,
+            (Some(audio), false) => audio.get_pos().as_secs_f64(),
+            (None, false) => self.start + self.started.elapsed().as_secs_f64(),
+        }
+    }
+}
+
+/// Picks the clock for a windowed session: previews play in real time and can be paused and
+/// scrubbed, and anything being recorded steps a frame at a time.
+pub struct Transport {
+    frame_rate: FrameRate,
+    /// `None` when not previewing, in which case everything is recorded.
+    playback: Option<PlaybackClock>,
+    paused: Option<ManualClock>,
+    /// The recording in progress, and where it started.
+    take: Option<(f64, FixedStep)>,
+}
+impl Transport {
+    /// Records everything from `start` on.
+    pub fn recording(start: f64, frame_rate: FrameRate) -> Self {
+        Self {
+            frame_rate,
+            playback: None,
+            paused: None,
+            take: Some((start, FixedStep::new(start, frame_rate))),
+        }
+    }
+
+    /// Plays from `start` on, along with `audio` if there is any.
+    pub fn preview(audio: Option<&str>, start: f64, frame_rate: FrameRate) -> Result<Self> {
+        Ok(Self {
+            frame_rate,
+            playback: Some(PlaybackClock::new(audio, start)?),
+            paused: None,
+            take: None,
+        })
+    }
+
+    pub fn clock(&mut self) -> &mut dyn Clock {
+        if let Some((_, take)) = &mut self.take {
+            return take;
+        }
+        if let Some(paused) = &mut self.paused {
+            return paused;
+        }
+        self.playback.as_mut().unwrap()
+    }
+
+    /// Where the recording in progress started, if there is one.
+    pub fn take_start(&self) -> Option<f64> {
+        self.take.as_ref().map(|&(start, _)| start)
+    }
+
+    /// Starts stepping through frames from the current time, for recording them. The audio can't
+    /// keep up with that, so it's held until [`Self::stop_take`]. Returns where the take starts.
+    pub fn start_take(&mut self) -> f64 {
+        let start = self.clock().time();
+        if self.paused.is_none() {
+            if let Some(playback) = &mut self.playback {
+                playback.pause();
+            }
+        }
+        self.take = Some((start, FixedStep::new(start, self.frame_rate)));
+        start
+    }
+
+    /// Carries on playing (or staying paused) from wherever the recording got to.
+    pub fn stop_take(&mut self) -> Result<()> {
+        let Some((_, mut take)) = self.take.take() else {
+            return Ok(());
+        };
+        let time = take.time();
+        match (&mut self.paused, &mut self.playback) {
+            (Some(paused), _) => paused.set(time),
+            (None, Some(playback)) => playback.resume(time)?,
+            (None, None) => {}
+        }
+        Ok(())
+    }
+
+    /// Pauses or unpauses a preview. Does nothing while recording.
+    pub fn toggle_pause(&mut self) -> Result<()> {
+        let (None, Some(playback)) = (&self.take, &mut self.playback) else {
+            return Ok(());
+        };
+        match self.paused.take() {
+            Some(mut paused) => playback.resume(paused.time())?,
+            None => {
+                let time = playback.time();
+                playback.pause();
+                self.paused = Some(ManualClock::new(time));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.is_some()
+    }
+
+    /// Jumps `by` seconds, but never to before the start of the track. Does nothing while
+    /// recording.
+    pub fn scrub(&mut self, by: f64) -> Result<()> {
+        let (None, Some(playback)) = (&self.take, &mut self.playback) else {
+            return Ok(());
+        };
+        match &mut self.paused {
+            Some(paused) => {
+                let time = (paused.time() + by).max(0.0);
+                paused.set(time);
+            }
+            None => {
+                let time = (playback.time() + by).max(0.0);
+                playback.resume(time)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> FrameRate {
+        FrameRate::parse(text).unwrap()
+    }
+
+    #[test]
+    fn parse_ntsc() {
+        assert_eq!(parse("29.97"), FrameRate::new(30000, 1001));
+        assert_eq!(parse("23.976"), FrameRate::new(24000, 1001));
+        assert_eq!(parse("59.94"), FrameRate::new(60000, 1001));
+        assert_eq!(parse("119.88"), FrameRate::new(120000, 1001));
+        // whole and other decimal rates stay as they are
+        assert_eq!(parse("60"), FrameRate::new(60, 1));
+        assert_eq!(parse("24.0"), FrameRate::new(24, 1));
+        assert_eq!(parse("12.5"), FrameRate::new(25, 2));
+    }
+
+    #[test]
+    fn parse_ratio() {
+        assert_eq!(parse("30000/1001"), FrameRate::new(30000, 1001));
+        assert_eq!(parse(" 24000 / 1001 "), FrameRate::new(24000, 1001));
+        assert_eq!(parse("60/1"), FrameRate::new(60, 1));
+        // reduced, so equal rates compare equal
+        assert_eq!(parse("120/2"), FrameRate::new(60, 1));
+        assert_eq!(parse("50/4"), parse("12.5"));
+    }
+
+    #[test]
+    fn parse_rejects() {
+        for text in [
+            "", "0", "0.0", "-30", "inf", "NaN", "fast", "0/1", "30/0", "0/0", "x/0", "30/",
+            "/1001", "30/1.5", "1/2/3",
+        ] {
+            assert!(FrameRate::parse(text).is_err(), "{text:?} parsed");
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(FrameRate::new(60, 1).to_string(), "60");
+        assert_eq!(FrameRate::new(30000, 1001).to_string(), "30000/1001");
+        for text in ["29.97", "60", "12.5"] {
+            assert_eq!(parse(&parse(text).to_string()), parse(text));
+        }
+    }
+
+    #[test]
+    fn frame_time_is_exact() {
+        let rate = FrameRate::new(30000, 1001);
+        // 10 hours of 29.97, where every 30000th frame starts on a whole second
+        assert_eq!(rate.frame_time(30000 * 36), 1001.0 * 36.0);
+        assert_eq!(rate.frame_time(1_000_000_000 * 30000), 1_001_000_000_000.0);
+
+        // everywhere else it's the nearest float to the exact ratio, however far in
+        for frame in [1, 1001, 29_999, 1_078_921, 4_000_000_000, 123_456_789_012] {
+            let ticks = frame as u128 * 1001;
+            let exact = (ticks / 30000) as f64 + (ticks % 30000) as f64 / 30000.0;
+            let time = rate.frame_time(frame);
+            assert!(
+                (time - exact).abs() <= exact * f64::EPSILON,
+                "frame {frame}"
+            );
+        }
+
+        // adding up frame durations instead drifts
+        let mut added = 0.0;
+        for _ in 0..1_078_921 {
+            added += rate.frame_duration();
+        }
+        assert_ne!(added, rate.frame_time(1_078_921));
+    }
+
+    #[test]
+    fn fixed_step() {
+        let mut clock = FixedStep::new(2.0, FrameRate::new(24000, 1001));
+        assert_eq!(clock.time(), 2.0);
+        for _ in 0..24000 {
+            clock.advance();
+        }
+        assert_eq!(clock.time(), 1003.0);
+    }
+}
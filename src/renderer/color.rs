@@ -3,6 +3,8 @@ use std::f32::consts::TAU;
 use glam::{vec3, vec4, Vec3, Vec4};
 use rand::{thread_rng, Rng};
 
+use super::output::linear_to_srgb;
+
 pub type Color = Vec4;
 
 pub fn sinebow(t: f32) -> Color {
@@ -29,3 +31,47 @@ pub fn hueflip(color: Color) -> Color {
     let min_component = color.x.min(color.y).min(color.z);
     (Vec3::splat(max_component + min_component) - vec3(color.x, color.y, color.z)).extend(color.w)
 }
+
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts a color, as the apps write them (sRGB encoded), into oklab, where equal distances
+/// look about equally different. Alpha is left alone.
+/// See https://bottosson.github.io/posts/oklab/
+pub fn to_oklab(color: Color) -> Vec4 {
+    let rgb = vec3(color.x, color.y, color.z).map(srgb_to_linear);
+    let lms = vec3(
+        vec3(0.41222146, 0.53633255, 0.051445995).dot(rgb),
+        vec3(0.2119035, 0.6806995, 0.10739696).dot(rgb),
+        vec3(0.08830246, 0.28171885, 0.6299787).dot(rgb),
+    )
+    .map(f32::cbrt);
+    vec4(
+        vec3(0.21045426, 0.7936178, -0.004072047).dot(lms),
+        vec3(1.9779985, -2.4285922, 0.4505937).dot(lms),
+        vec3(0.025904037, 0.78277177, -0.80867577).dot(lms),
+        color.w,
+    )
+}
+
+pub fn from_oklab(lab: Vec4) -> Color {
+    let lab3 = vec3(lab.x, lab.y, lab.z);
+    let lms = vec3(
+        vec3(1.0, 0.39633778, 0.21580376).dot(lab3),
+        vec3(1.0, -0.105561346, -0.06385417).dot(lab3),
+        vec3(1.0, -0.08948418, -1.2914855).dot(lab3),
+    )
+    .map(|v| v * v * v);
+    vec3(
+        vec3(4.0767417, -3.3077116, 0.23096994).dot(lms),
+        vec3(-1.268438, 2.6097574, -0.34131938).dot(lms),
+        vec3(-0.0041960864, -0.7034186, 1.7076147).dot(lms),
+    )
+    .map(linear_to_srgb)
+    .extend(lab.w)
+}
@@ -0,0 +1,116 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::{
+    command_buffer::{allocator::CommandBufferAllocator, AutoCommandBufferBuilder},
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, PersistentDescriptorSet, WriteDescriptorSet,
+    },
+    device::Device,
+    image::{view::ImageView, Image},
+    pipeline::{
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+/// Box filters supersampled frames down to the output size on the gpu, so only output sized frames
+/// ever get read back.
+pub struct Downsampler {
+    pipeline: Arc<ComputePipeline>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    factor: u32,
+}
+impl Downsampler {
+    pub fn new(
+        device: Arc<Device>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        factor: u32,
+    ) -> Result<Self> {
+        let stage = PipelineShaderStageCreateInfo::new(
+            shader::load(device.clone())?.entry_point("main").unwrap(),
+        );
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                .into_pipeline_layout_create_info(device.clone())?,
+        )?;
+        let pipeline = ComputePipeline::new(
+            device,
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )?;
+        Ok(Self {
+            pipeline,
+            descriptor_set_allocator,
+            factor,
+        })
+    }
+
+    /// Binds a supersampled image and the output sized image it's downsampled into. Both need
+    /// [`ImageUsage::STORAGE`](vulkano::image::ImageUsage::STORAGE).
+    pub fn bind(&self, src: Arc<Image>, dst: Arc<Image>) -> Result<Arc<PersistentDescriptorSet>> {
+        Ok(PersistentDescriptorSet::new(
+            &self.descriptor_set_allocator,
+            self.pipeline.layout().set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::image_view(0, ImageView::new_default(src)?),
+                WriteDescriptorSet::image_view(1, ImageView::new_default(dst)?),
+            ],
+            [],
+        )?)
+    }
+
+    /// Records downsampling into a `width`x`height` image, with images from [`Self::bind`].
+    pub fn downsample<L, A: CommandBufferAllocator + 'static>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+        images: Arc<PersistentDescriptorSet>,
+        [width, height]: [u32; 2],
+    ) -> Result<()> {
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                images,
+            )?
+            .push_constants(self.pipeline.layout().clone(), 0, self.factor)?
+            .dispatch([width.div_ceil(8), height.div_ceil(8), 1])?;
+        Ok(())
+    }
+}
+
+mod shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: r"
+            #version 460
+
+            layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+            layout(set = 0, binding = 0, rgba32f) uniform readonly image2D src;
+            layout(set = 0, binding = 1, rgba32f) uniform writeonly image2D dst;
+
+            layout(push_constant) uniform settings {
+                uint factor;
+            };
+
+            void main() {
+                ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+                if (any(greaterThanEqual(pixel, imageSize(dst)))) { return; }
+
+                ivec2 corner = pixel * int(factor);
+                vec4 sum = vec4(0.0);
+                for (int y = 0; y < int(factor); y++) {
+                    for (int x = 0; x < int(factor); x++) {
+                        sum += imageLoad(src, corner + ivec2(x, y));
+                    }
+                }
+                imageStore(dst, pixel, sum / float(factor * factor));
+            }
+        ",
+    }
+}
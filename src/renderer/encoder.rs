@@ -0,0 +1,184 @@
+use std::{
+    io::ErrorKind,
+    process::{Child, ChildStdin, Command, Stdio},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::clock::FrameRate;
+
+#[derive(Clone, Debug)]
+pub enum Quality {
+    /// Constant rate factor; lower is better. Around 18 is visually lossless for x264.
+    Crf(u32),
+    /// Target bitrate in ffmpeg notation, like `"20M"`.
+    Bitrate(String),
+}
+
+/// Everything passed to ffmpeg when encoding and muxing.
+#[derive(Clone, Debug)]
+pub struct EncoderSettings {
+    pub codec: String,
+    pub quality: Quality,
+    /// Encoder speed preset (`-preset`), if the codec has any.
+    pub preset: Option<String>,
+    /// Pixel format of the encoded video. The pipe into ffmpeg is always rgba.
+    pub pixel_format: String,
+    /// Muxer name (`-f`). If `None`, ffmpeg guesses from the output's extension.
+    pub container: Option<String>,
+    pub audio_codec: String,
+    pub audio_bitrate: Option<String>,
+    pub output: String,
+}
+impl EncoderSettings {
+    /// Fast to encode, for checking a render.
+    pub fn preview(output: impl Into<String>) -> Self {
+        Self {
+            codec: "libx264".into(),
+            quality: Quality::Crf(23),
+            preset: Some("ultrafast".into()),
+            pixel_format: "yuv420p".into(),
+            container: None,
+            audio_codec: "aac".into(),
+            audio_bitrate: None,
+            output: output.into(),
+        }
+    }
+    /// Slow to encode, for final deliverables.
+    pub fn high_quality(output: impl Into<String>) -> Self {
+        Self {
+            codec: "libx264".into(),
+            quality: Quality::Crf(12),
+            preset: Some("veryslow".into()),
+            pixel_format: "yuv420p".into(),
+            container: None,
+            audio_codec: "aac".into(),
+            audio_bitrate: Some("320k".into()),
+            output: output.into(),
+        }
+    }
+    pub fn from_profile(profile: &str, output: impl Into<String>) -> Result<Self> {
+        match profile {
+            "preview" => Ok(Self::preview(output)),
+            "final" => Ok(Self::high_quality(output)),
+            _ => bail!("unknown encoder profile `{profile}`; expected `preview` or `final`"),
+        }
+    }
+
+    /// Overrides a single setting by name, as given on the command line.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "codec" => self.codec = value.into(),
+            "crf" => {
+                self.quality = Quality::Crf(
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid crf `{value}`"))?,
+                )
+            }
+            "bitrate" => self.quality = Quality::Bitrate(value.into()),
+            "preset" => self.preset = Some(value.into()).filter(|preset| preset != "none"),
+            "pix-fmt" => self.pixel_format = value.into(),
+            "container" => self.container = Some(value.into()),
+            "audio-codec" => self.audio_codec = value.into(),
+            "audio-bitrate" => self.audio_bitrate = Some(value.into()),
+            _ => bail!("unknown encoder setting `{key}`"),
+        }
+        Ok(())
+    }
+
+    /// Spawns ffmpeg, ready to receive raw rgba frames of the given size and rate through its
+    /// stdin, and encode them into `file` without audio.
+    pub fn spawn_video_stream(
+        &self,
+        width: usize,
+        height: usize,
+        frame_rate: FrameRate,
+        file: impl AsRef<str>,
+    ) -> Result<(Child, ChildStdin)> {
+        let mut args: Vec<String> = [
+            "-f",
+            "rawvideo",
+            "-pix_fmt",
+            "rgba",
+            "-s:v",
+            &format!("{width}x{height}"),
+            "-r",
+            &frame_rate.to_string(),
+            "-i",
+            "pipe:",
+            "-c:v",
+            &self.codec,
+            "-pix_fmt",
+            &self.pixel_format,
+        ]
+        .map(String::from)
+        .into();
+        match &self.quality {
+            Quality::Crf(crf) => args.extend(["-crf".into(), crf.to_string()]),
+            Quality::Bitrate(bitrate) => args.extend(["-b:v".into(), bitrate.clone()]),
+        }
+        if let Some(preset) = &self.preset {
+            args.extend(["-preset".into(), preset.clone()]);
+        }
+        if let Some(container) = &self.container {
+            args.extend(["-f".into(), container.clone()]);
+        }
+        args.extend(["-y".into(), file.as_ref().into()]);
+
+        let mut child = spawn_ffmpeg(args)?;
+        let pixel_input = child.stdin.take().unwrap();
+        Ok((child, pixel_input))
+    }
+
+    /// Muxes an audio track into an already encoded video, writing the result to
+    /// [`Self::output`]. The video stream is copied, not re-encoded.
+    pub fn merge_av(
+        &self,
+        audio: impl AsRef<str>,
+        audio_offset: f64,
+        video: impl AsRef<str>,
+    ) -> Result<Child> {
+        let mut args: Vec<String> = [
+            "-ss",
+            "0.0",
+            "-i",
+            video.as_ref(),
+            "-ss",
+            &format!("{audio_offset}"),
+            "-i",
+            audio.as_ref(),
+            "-c:v",
+            "copy",
+            "-c:a",
+            &self.audio_codec,
+        ]
+        .map(String::from)
+        .into();
+        if let Some(bitrate) = &self.audio_bitrate {
+            args.extend(["-b:a".into(), bitrate.clone()]);
+        }
+        if let Some(container) = &self.container {
+            args.extend(["-f".into(), container.clone()]);
+        }
+        args.extend(["-shortest".into(), "-y".into(), self.output.clone()]);
+
+        spawn_ffmpeg(args)
+    }
+}
+
+fn spawn_ffmpeg(args: Vec<String>) -> Result<Child> {
+    Command::new("ffmpeg")
+        .args(args)
+        .stdin(Stdio::piped())
+        // .stdout(Stdio::null())
+        // .stdin(Stdio::null())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => anyhow!(
+                "ffmpeg wasn't found on PATH; install it, or record with `--sink y4m` or \
+                 `--sink avi`, which don't need it"
+            ),
+            _ => anyhow!("could not start ffmpeg: {e}"),
+        })
+}
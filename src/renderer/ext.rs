@@ -6,23 +6,16 @@ use std::{
 };
 
 use anyhow::Result;
-use bytemuck::Pod;
 use image::Rgba32FImage;
 use vulkano::{
-    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
     command_buffer::{
-        allocator::CommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferExecFuture,
-        CommandBufferUsage, CopyBufferToImageInfo,
+        allocator::CommandBufferAllocator, AutoCommandBufferBuilder, BlitImageInfo,
+        ClearColorImageInfo, CopyBufferToImageInfo, ImageBlit,
     },
-    device::{Device, Queue},
-    format::Format,
-    image::{Image, ImageCreateInfo, ImageLayout, ImageType, ImageUsage},
+    format::{ClearColorValue, Format, FormatFeatures},
+    image::{sampler::Filter, Image, ImageCreateInfo, ImageType, ImageUsage},
     memory::allocator::{AllocationCreateInfo, MemoryAllocator, MemoryTypeFilter},
-    sync::{
-        self,
-        future::{FenceSignalFuture, NowFuture},
-        GpuFuture,
-    },
 };
 
 use super::texture::PixelType;
@@ -57,6 +50,7 @@ pub trait CommandBufferExt {
         usage: ImageUsage,
     ) -> Result<(&mut Self, Arc<Image>)>;
 
+    #[allow(clippy::type_complexity)]
     fn create_blank_image<C: PixelType>(
         &mut self,
         width: u32,
@@ -66,6 +60,10 @@ pub trait CommandBufferExt {
         buffer_memory_filter: MemoryTypeFilter,
         buffer_usage: BufferUsage,
     ) -> Result<(&mut Self, Arc<Image>, Subbuffer<[C]>)>;
+
+    /// Clears `dst` to black, then scales `src` into the middle of it, as large as it fits without
+    /// changing its aspect ratio.
+    fn blit_letterboxed(&mut self, src: Arc<Image>, dst: Arc<Image>) -> Result<&mut Self>;
 }
 
 impl<L, A: CommandBufferAllocator + 'static> CommandBufferExt for AutoCommandBufferBuilder<L, A> {
@@ -97,7 +95,7 @@ impl<L, A: CommandBufferAllocator + 'static> CommandBufferExt for AutoCommandBuf
                     | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
                 ..Default::default()
             },
-            image_data.into_iter().copied(),
+            image_data.iter().copied(),
         )?;
         self.copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(
             staging_buffer,
@@ -146,6 +144,45 @@ impl<L, A: CommandBufferAllocator + 'static> CommandBufferExt for AutoCommandBuf
         .unwrap();
         Ok((self, image, staging_buffer))
     }
+
+    fn blit_letterboxed(&mut self, src: Arc<Image>, dst: Arc<Image>) -> Result<&mut Self> {
+        let [src_width, src_height, _] = src.extent();
+        let [dst_width, dst_height, _] = dst.extent();
+        let scale =
+            (dst_width as f64 / src_width as f64).min(dst_height as f64 / src_height as f64);
+        let width = ((src_width as f64 * scale).round() as u32).clamp(1, dst_width);
+        let height = ((src_height as f64 * scale).round() as u32).clamp(1, dst_height);
+        let x = (dst_width - width) / 2;
+        let y = (dst_height - height) / 2;
+
+        // not every format can be filtered linearly, float ones especially
+        let filter = if src
+            .format_features()
+            .intersects(FormatFeatures::SAMPLED_IMAGE_FILTER_LINEAR)
+        {
+            Filter::Linear
+        } else {
+            Filter::Nearest
+        };
+
+        self.clear_color_image(ClearColorImageInfo {
+            clear_value: ClearColorValue::Float([0.0, 0.0, 0.0, 1.0]),
+            ..ClearColorImageInfo::image(dst.clone())
+        })?;
+        self.blit_image(BlitImageInfo {
+            regions: [ImageBlit {
+                src_subresource: src.subresource_layers(),
+                src_offsets: [[0, 0, 0], [src_width, src_height, 1]],
+                dst_subresource: dst.subresource_layers(),
+                dst_offsets: [[x, y, 0], [x + width, y + height, 1]],
+                ..Default::default()
+            }]
+            .into(),
+            filter,
+            ..BlitImageInfo::images(src, dst)
+        })?;
+        Ok(self)
+    }
 }
 
 // generic af trait
@@ -1,20 +1,44 @@
-use std::f32::consts::{E, PI};
+use std::{
+    f32::consts::{E, PI},
+    fmt,
+    sync::Arc,
+};
 
-use crate::ext::LerpExt;
+use glam::{Quat, Vec2, Vec3, Vec4};
 
+use super::tempo::{TempoMap, TimePoint};
+
+/// The shape of an ease, going from 0 at `t == 0` to 1 at `t == 1`. Some of them overshoot on the
+/// way.
+#[derive(Clone)]
 pub enum EasingType {
     Constant,
     Linear,
     Sine,
     Power(f32),
     Exponential(f32),
+    /// `cubic-bezier(x1, y1, x2, y2)`, as in css. The x coordinates are kept within 0-1, so there's
+    /// always exactly one point on the curve for each `t`.
+    CubicBezier(f32, f32, f32, f32),
+    /// Winds up with a spring-like wobble.
+    Elastic,
+    Bounce,
+    /// Pulls back before going, by the given overshoot. 1.70158 is the usual amount, for about a
+    /// tenth of the distance.
+    Back(f32),
+    /// A quarter circle.
+    Circular,
+    /// Jumps in this many equal steps, at the end of each.
+    Steps(u32),
+    /// Anything else. It's given `t`, and should go from 0 to 1 as `t` does.
+    Custom(Arc<dyn Fn(f32) -> f32 + Send + Sync>),
 }
 impl EasingType {
     pub fn sample(&self, t: f32) -> f32 {
         match self {
             EasingType::Constant => 0.0,
             EasingType::Linear => t,
-            EasingType::Sine => -(t * PI * 0.5).cos() * 0.5 + 0.5,
+            EasingType::Sine => 1.0 - (t * PI * 0.5).cos(),
             EasingType::Power(power) => t.powf(*power),
             EasingType::Exponential(v) => {
                 // f(x) == x^2 as v approaches zero
@@ -35,10 +59,108 @@ impl EasingType {
 
                 (exp_value - derivative_factor * t) / (1.0 - derivative_factor)
             }
+            EasingType::CubicBezier(x1, y1, x2, y2) => {
+                let s = bezier_parameter(x1.clamp(0.0, 1.0), x2.clamp(0.0, 1.0), t);
+                bezier(*y1, *y2, s)
+            }
+            EasingType::Elastic => {
+                if t <= 0.0 || t >= 1.0 {
+                    return t.clamp(0.0, 1.0);
+                }
+                -(2.0f32).powf(10.0 * t - 10.0) * ((t * 10.0 - 10.75) * PI * 2.0 / 3.0).sin()
+            }
+            // bounces are easiest to write landing, so flip one
+            EasingType::Bounce => 1.0 - bounce_out(1.0 - t),
+            EasingType::Back(overshoot) => (overshoot + 1.0) * t * t * t - overshoot * t * t,
+            EasingType::Circular => 1.0 - (1.0 - t * t).max(0.0).sqrt(),
+            EasingType::Steps(steps) => {
+                let steps = (*steps).max(1) as f32;
+                (t * steps).floor().min(steps) / steps
+            }
+            EasingType::Custom(f) => f(t),
+        }
+    }
+}
+impl fmt::Debug for EasingType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EasingType::Constant => write!(f, "Constant"),
+            EasingType::Linear => write!(f, "Linear"),
+            EasingType::Sine => write!(f, "Sine"),
+            EasingType::Power(power) => write!(f, "Power({power})"),
+            EasingType::Exponential(v) => write!(f, "Exponential({v})"),
+            EasingType::CubicBezier(x1, y1, x2, y2) => {
+                write!(f, "CubicBezier({x1}, {y1}, {x2}, {y2})")
+            }
+            EasingType::Elastic => write!(f, "Elastic"),
+            EasingType::Bounce => write!(f, "Bounce"),
+            EasingType::Back(overshoot) => write!(f, "Back({overshoot})"),
+            EasingType::Circular => write!(f, "Circular"),
+            EasingType::Steps(steps) => write!(f, "Steps({steps})"),
+            EasingType::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+/// One coordinate of a cubic bezier from (0, 0) to (1, 1), with control points `a` and `b`, at
+/// `s` along it.
+fn bezier(a: f32, b: f32, s: f32) -> f32 {
+    let r = 1.0 - s;
+    3.0 * r * r * s * a + 3.0 * r * s * s * b + s * s * s
+}
+
+/// Finds how far along the curve it reaches `x`. Newton's method gets there in a few steps for
+/// most curves, and bisection takes over for the flat bits where it can't.
+fn bezier_parameter(x1: f32, x2: f32, x: f32) -> f32 {
+    let x = x.clamp(0.0, 1.0);
+    let mut s = x;
+    for _ in 0..8 {
+        let error = bezier(x1, x2, s) - x;
+        if error.abs() < 1e-6 {
+            return s;
+        }
+        let r = 1.0 - s;
+        let slope = 3.0 * r * r * x1 + 6.0 * r * s * (x2 - x1) + 3.0 * s * s * (1.0 - x2);
+        if slope.abs() < 1e-6 {
+            break;
+        }
+        s -= error / slope;
+    }
+
+    // x only ever grows along the curve, so this always finds it
+    let (mut low, mut high) = (0.0, 1.0);
+    s = x;
+    for _ in 0..32 {
+        if bezier(x1, x2, s) < x {
+            low = s;
+        } else {
+            high = s;
         }
+        s = (low + high) * 0.5;
     }
+    s
 }
 
+fn bounce_out(t: f32) -> f32 {
+    const N: f32 = 7.5625;
+    const D: f32 = 2.75;
+    if t < 1.0 / D {
+        N * t * t
+    } else if t < 2.0 / D {
+        let t = t - 1.5 / D;
+        N * t * t + 0.75
+    } else if t < 2.5 / D {
+        let t = t - 2.25 / D;
+        N * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / D;
+        N * t * t + 0.984375
+    }
+}
+
+/// Which end of the ease the shape is at. `InOut` squashes it into the first half and mirrors it
+/// into the second.
+#[derive(Clone, Debug)]
 pub enum EasingDirection {
     In,
     Out,
@@ -56,11 +178,12 @@ impl EasingDirection {
         match self {
             EasingDirection::In => 1.0,
             EasingDirection::Out => -1.0,
+            // each half only covers half the distance
             EasingDirection::InOut => {
                 if t < 0.5 {
-                    1.0
+                    0.5
                 } else {
-                    -1.0
+                    -0.5
                 }
             }
         }
@@ -80,59 +203,928 @@ impl EasingDirection {
     }
 }
 
+#[derive(Clone, Debug)]
 pub struct Easing {
     pub etype: EasingType,
     pub direction: EasingDirection,
 }
 impl Easing {
+    pub fn new(etype: EasingType, direction: EasingDirection) -> Self {
+        Self { etype, direction }
+    }
+    pub fn linear() -> Self {
+        Self::new(EasingType::Linear, EasingDirection::In)
+    }
+    /// Stays on a key until the next one.
+    pub fn hold() -> Self {
+        Self::new(EasingType::Constant, EasingDirection::In)
+    }
+    /// The css `cubic-bezier(x1, y1, x2, y2)` timing function.
+    pub fn cubic_bezier(x1: f32, y1: f32, x2: f32, y2: f32) -> Self {
+        Self::new(EasingType::CubicBezier(x1, y1, x2, y2), EasingDirection::In)
+    }
+
     pub fn apply(&self, t: f32) -> f32 {
         self.direction.get_ease_factor(t) * self.etype.sample(self.direction.get_sample_point(t))
             + self.direction.get_ease_offset(t)
     }
 }
 
+/// Anything that can be keyed.
+pub trait Tween: Clone {
+    /// `mix` of the way from here to `to`. Easings that overshoot, and linear extrapolation, take
+    /// `mix` outside 0-1.
+    fn tween(&self, to: &Self, mix: f32) -> Self;
+
+    /// Moved on by `cycles` times however far it is from `from` to `to`.
+    fn accumulate(&self, from: &Self, to: &Self, cycles: f32) -> Self;
+
+    /// Like [`Self::tween`], but curving through the keys either side rather than going straight
+    /// between them. Keys are treated as if they were evenly spaced. At the ends of a sequence,
+    /// `before` is `from` and `after` is `to`.
+    fn tween_smooth(before: &Self, from: &Self, to: &Self, after: &Self, mix: f32) -> Self {
+        let _ = (before, after);
+        from.tween(to, mix)
+    }
+}
+
+macro_rules! tween_linearly {
+    ($($t:ty),*) => {$(
+        impl Tween for $t {
+            fn tween(&self, to: &Self, mix: f32) -> Self {
+                *self + (*to - *self) * mix
+            }
+            fn accumulate(&self, from: &Self, to: &Self, cycles: f32) -> Self {
+                *self + (*to - *from) * cycles
+            }
+            // catmull-rom
+            fn tween_smooth(before: &Self, from: &Self, to: &Self, after: &Self, mix: f32) -> Self {
+                let (p0, p1, p2, p3) = (*before, *from, *to, *after);
+                (p1 * 2.0
+                    + (p2 - p0) * mix
+                    + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * (mix * mix)
+                    + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * (mix * mix * mix))
+                    * 0.5
+            }
+        }
+    )*};
+}
+tween_linearly!(f32, Vec2, Vec3, Vec4);
+
+/// Slerp, flipping the sign of `to` first when that's the shorter way round. Written as turning by
+/// a fraction of the angle between them, so an easing that overshoots carries on past the key.
+impl Tween for Quat {
+    fn tween(&self, to: &Self, mix: f32) -> Self {
+        let (axis, angle) = shortest_turn(*self, *to).to_axis_angle();
+        (Quat::from_axis_angle(axis, angle * mix) * *self).normalize()
+    }
+    fn accumulate(&self, from: &Self, to: &Self, cycles: f32) -> Self {
+        let (axis, angle) = shortest_turn(*from, *to).to_axis_angle();
+        (Quat::from_axis_angle(axis, angle * cycles) * *self).normalize()
+    }
+    // squad
+    fn tween_smooth(before: &Self, from: &Self, to: &Self, after: &Self, mix: f32) -> Self {
+        let from_control = squad_control(*before, *from, *to);
+        let to_control = squad_control(*from, *to, *after);
+        let path = from.tween(to, mix);
+        let control = from_control.tween(&to_control, mix);
+        path.tween(&control, 2.0 * mix * (1.0 - mix))
+    }
+}
+
+/// The rotation that takes `from` to `to`, around the short way.
+fn shortest_turn(from: Quat, to: Quat) -> Quat {
+    let to = if from.dot(to) < 0.0 { -to } else { to };
+    to * from.inverse()
+}
+
+/// Where a squad curve through `q` bends towards, given the keys either side.
+fn squad_control(before: Quat, q: Quat, after: Quat) -> Quat {
+    // a rotation's log is its axis scaled by half the angle, once it's on the short side
+    let log = |q: Quat| {
+        let q = if q.w < 0.0 { -q } else { q };
+        let (axis, angle) = q.to_axis_angle();
+        axis * angle * 0.5
+    };
+    let inverse = q.inverse();
+    let tangent = -(log(inverse * after) + log(inverse * before)) * 0.25;
+    (q * Quat::from_scaled_axis(tangent * 2.0)).normalize()
+}
+
+struct Keyframe<T> {
+    time: f32,
+    /// Where in the music the key was put, if it was, so it can be moved to follow the music.
+    at: Option<TimePoint>,
+    value: T,
+    /// How it gets from this key to the next.
+    easing: Easing,
+}
+
+/// What a sequence does before its first key or after its last.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Extrapolation {
+    /// Stays on the first or last key.
+    #[default]
+    Hold,
+    /// Starts over from the other end.
+    Loop,
+    /// Plays backwards, then forwards again, and so on.
+    PingPong,
+    /// Loops, but each time around carries on from where the last one ended, so something keyed
+    /// to go from 0 to 1 goes from 1 to 2 the next time.
+    LoopWithOffset,
+    /// Carries on in a straight line, at the speed of the first or last two keys.
+    Linear,
+}
+
+/// Values keyed at points in time, and eased between. Before the first key and after the last, it
+/// holds still on them, unless told to do something else there.
+///
+/// ```ignore
+/// let fade = KeyframeSequence::new()
+///     .key(0.0, 0.0, Easing::new(EasingType::Sine, EasingDirection::InOut))
+///     .key(4.0, 1.0, Easing::linear());
+/// let opacity = fade.sample(context.beat as f32);
+///
+/// // bars count whole bars, so keys from 0 to 1 that loop repeat every bar
+/// let pulse = KeyframeSequence::new()
+///     .key(0.0, 1.0, Easing::new(EasingType::Power(2.0), EasingDirection::Out))
+///     .key(1.0, 0.0, Easing::linear())
+///     .extrapolate(Extrapolation::Loop);
+/// let brightness = pulse.sample(context.bar as f32);
+///
+/// // keys put in the music are in beats, so they follow it whatever its tempo
+/// let tempo = context.tempo();
+/// let rise = KeyframeSequence::new()
+///     .key_at(tempo, TimePoint::Bar(8, 0.0), 0.0, Easing::linear())
+///     .key_at(tempo, TimePoint::parse("12:2:240")?, 1.0, Easing::linear())
+///     .key_at(tempo, TimePoint::Seconds(30.5), 0.0, Easing::linear());
+/// let height = rise.sample(context.beat as f32);
+/// ```
 pub struct KeyframeSequence<T> {
-    samples: Vec<(f32, T, Easing)>,
+    keys: Vec<Keyframe<T>>,
+    before: Extrapolation,
+    after: Extrapolation,
+    time_offset: f32,
+    time_scale: f32,
+    smooth: bool,
 }
 impl<T> KeyframeSequence<T> {
-    pub fn sort(&mut self) {
-        self.samples.sort_unstable_by(|l, r| l.0.total_cmp(&r.0));
+    pub fn new() -> Self {
+        Self {
+            keys: Vec::new(),
+            before: Extrapolation::Hold,
+            after: Extrapolation::Hold,
+            time_offset: 0.0,
+            time_scale: 1.0,
+            smooth: false,
+        }
+    }
+
+    /// Adds a key at `time`, with `easing` being how it moves on to the next one.
+    pub fn key(mut self, time: f32, value: T, easing: Easing) -> Self {
+        self.insert(time, value, easing);
+        self
+    }
+
+    /// Keys can go in any order. Two keys at the same time make a jump, from the one added first
+    /// to the one added after.
+    pub fn insert(&mut self, time: f32, value: T, easing: Easing) {
+        self.insert_key(Keyframe {
+            time,
+            at: None,
+            value,
+            easing,
+        });
+    }
+
+    /// Adds a key at a place in the music, by bars:beats:ticks, beats or seconds, worked out in
+    /// beats through `tempo`. The sequence should then be sampled in beats.
+    pub fn key_at(mut self, tempo: &TempoMap, at: TimePoint, value: T, easing: Easing) -> Self {
+        self.insert_at(tempo, at, value, easing);
+        self
+    }
+
+    pub fn insert_at(&mut self, tempo: &TempoMap, at: TimePoint, value: T, easing: Easing) {
+        self.insert_key(Keyframe {
+            time: at.beat(tempo) as f32,
+            at: Some(at),
+            value,
+            easing,
+        });
+    }
+
+    fn insert_key(&mut self, key: Keyframe<T>) {
+        let index = self.keys.partition_point(|other| other.time <= key.time);
+        self.keys.insert(index, key);
     }
-    pub fn sample(&mut self, t: f32) -> T
+
+    /// Works out the keys put in the music again, for a piece whose tempo map has changed, so
+    /// that they land in the same places in it. Keys in seconds stay on the same moment of the
+    /// track, and keys in bars follow any change of meter.
+    pub fn retime(&mut self, tempo: &TempoMap) {
+        for key in &mut self.keys {
+            if let Some(at) = key.at {
+                key.time = at.beat(tempo) as f32;
+            }
+        }
+        // stable, so keys at the same time stay in the order they were added
+        self.keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+
+    /// What happens before the first key.
+    pub fn before(mut self, extrapolation: Extrapolation) -> Self {
+        self.before = extrapolation;
+        self
+    }
+
+    /// What happens after the last key.
+    pub fn after(mut self, extrapolation: Extrapolation) -> Self {
+        self.after = extrapolation;
+        self
+    }
+
+    /// What happens on both sides of the keys.
+    pub fn extrapolate(self, extrapolation: Extrapolation) -> Self {
+        self.before(extrapolation).after(extrapolation)
+    }
+
+    /// Moves the whole sequence so that its time 0 is at `offset`.
+    pub fn time_offset(mut self, offset: f32) -> Self {
+        self.time_offset = offset;
+        self
+    }
+
+    /// Stretches the whole sequence so that each unit of its time lasts `scale` outside. Negative
+    /// scales play it backwards, and 0 isn't allowed.
+    pub fn time_scale(mut self, scale: f32) -> Self {
+        assert!(
+            scale != 0.0,
+            "a keyframe sequence can't have a time scale of 0"
+        );
+        self.time_scale = scale;
+        self
+    }
+
+    /// Curves through the keys instead of going straight from one to the next: along a
+    /// catmull-rom spline for numbers and vectors, and by squad for rotations. Easings still apply
+    /// on top.
+    pub fn smooth(mut self) -> Self {
+        self.smooth = true;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The value at `t`, or `T::default()` if there are no keys at all.
+    pub fn sample(&self, t: f32) -> T
     where
-        T: Default + Clone + LerpExt<f32>,
+        T: Default + Tween,
     {
-        if self.samples.is_empty() {
+        let (Some(first), Some(last)) = (self.keys.first(), self.keys.last()) else {
             return T::default();
+        };
+        let t = (t - self.time_offset) / self.time_scale;
+        let extrapolation = if t < first.time {
+            self.before
+        } else if t >= last.time {
+            self.after
+        } else {
+            return self.interpolate(t);
+        };
+
+        let length = last.time - first.time;
+        // how many times round the keys it's been, and how far into the current time round
+        let cycles = ((t - first.time) / length).floor();
+        let phase = (t - first.time) - cycles * length;
+        match extrapolation {
+            // keys that all sit at the same time have nothing to repeat
+            _ if length <= 0.0 => self.interpolate(t),
+            Extrapolation::Hold => self.interpolate(t),
+            Extrapolation::Loop => self.interpolate(first.time + phase),
+            Extrapolation::PingPong if cycles.rem_euclid(2.0) == 1.0 => {
+                self.interpolate(last.time - phase)
+            }
+            Extrapolation::PingPong => self.interpolate(first.time + phase),
+            Extrapolation::LoopWithOffset => {
+                self.interpolate(first.time + phase)
+                    .accumulate(&first.value, &last.value, cycles)
+            }
+            Extrapolation::Linear => {
+                let (from, to) = match t < first.time {
+                    true => (&self.keys[0], &self.keys[1]),
+                    false => (
+                        &self.keys[self.keys.len() - 2],
+                        &self.keys[self.keys.len() - 1],
+                    ),
+                };
+                if to.time <= from.time {
+                    return self.interpolate(t);
+                }
+                from.value
+                    .tween(&to.value, (t - from.time) / (to.time - from.time))
+            }
         }
-        if t <= self.samples[0].0 {}
-        if t >= self.samples.last().unwrap().0 {
-            return self.samples.last().unwrap().1.clone();
-        }
+    }
 
-        let second_index = match self.samples.binary_search_by(|i| i.0.total_cmp(&t)) {
-            Ok(i) => i,
-            Err(i) => i,
-        };
-        if second_index == 0 {
-            return self.samples[0].1.clone();
+    /// The value at `t` in the sequence's own time, holding still outside the keys. There has to
+    /// be at least one.
+    fn interpolate(&self, t: f32) -> T
+    where
+        T: Tween,
+    {
+        let first = &self.keys[0];
+        let last = &self.keys[self.keys.len() - 1];
+        if t < first.time {
+            return first.value.clone();
         }
-        if second_index >= self.samples.len() {
-            return self.samples.last().unwrap().1.clone();
+        if t >= last.time {
+            return last.value.clone();
         }
-        let first_index = second_index - 1;
 
-        let first = &self.samples[first_index];
-        let second = &self.samples[second_index];
+        // first <= t < last, so there's always a key on either side
+        let next = self.keys.partition_point(|key| key.time <= t);
+        let from = &self.keys[next - 1];
+        let to = &self.keys[next];
 
-        let offset = first.0;
-        let distance = second.0 - first.0;
         // ranges from 0-1
-        let lerp_factor = (t - offset) / distance;
+        let lerp_factor = (t - from.time) / (to.time - from.time);
+        let mix = from.easing.apply(lerp_factor);
+
+        if self.smooth {
+            let before = &self.keys[next.saturating_sub(2)];
+            let after = &self.keys[(next + 1).min(self.keys.len() - 1)];
+            T::tween_smooth(&before.value, &from.value, &to.value, &after.value, mix)
+        } else {
+            from.value.tween(&to.value, mix)
+        }
+    }
+}
+impl<T> Default for KeyframeSequence<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::tempo::Meter;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    fn all_directions(etype: EasingType) -> [Easing; 3] {
+        [
+            EasingDirection::In,
+            EasingDirection::Out,
+            EasingDirection::InOut,
+        ]
+        .map(|direction| Easing::new(etype.clone(), direction))
+    }
+
+    // everything but constant starts at 0, ends at 1 and never goes backwards
+    fn check_shape(etype: EasingType) {
+        for easing in all_directions(etype) {
+            assert!(close(easing.apply(0.0), 0.0), "{easing:?} at 0");
+            assert!(close(easing.apply(1.0), 1.0), "{easing:?} at 1");
+            let mut last = 0.0;
+            for i in 0..=100 {
+                let eased = easing.apply(i as f32 / 100.0);
+                assert!(eased >= last - 1e-5, "{easing:?} goes backwards at {i}%");
+                last = eased;
+            }
+        }
+    }
+
+    #[test]
+    fn constant() {
+        let [into, out, in_out] = all_directions(EasingType::Constant);
+        for t in [0.0, 0.25, 0.75, 0.999] {
+            assert_eq!(into.apply(t), 0.0);
+        }
+        for t in [0.001, 0.25, 0.75, 1.0] {
+            assert_eq!(out.apply(t), 1.0);
+        }
+        assert_eq!(in_out.apply(0.25), 0.0);
+        assert_eq!(in_out.apply(0.75), 1.0);
+    }
+
+    #[test]
+    fn linear() {
+        check_shape(EasingType::Linear);
+        for easing in all_directions(EasingType::Linear) {
+            for t in [0.1, 0.25, 0.5, 0.9] {
+                assert!(close(easing.apply(t), t), "{easing:?} at {t}");
+            }
+        }
+    }
+
+    #[test]
+    fn sine() {
+        check_shape(EasingType::Sine);
+        let [into, out, in_out] = all_directions(EasingType::Sine);
+        assert!(close(into.apply(0.5), 1.0 - 0.5f32.sqrt()));
+        assert!(close(out.apply(0.5), 0.5f32.sqrt()));
+        assert!(close(in_out.apply(0.5), 0.5));
+        assert!(close(in_out.apply(0.25), 0.5 * into.apply(0.5)));
+    }
+
+    #[test]
+    fn power() {
+        check_shape(EasingType::Power(2.0));
+        check_shape(EasingType::Power(0.5));
+        let [into, out, in_out] = all_directions(EasingType::Power(3.0));
+        assert!(close(into.apply(0.5), 0.125));
+        assert!(close(out.apply(0.5), 0.875));
+        assert!(close(in_out.apply(0.25), 0.0625));
+        assert!(close(in_out.apply(0.75), 0.9375));
+    }
+
+    #[test]
+    fn exponential() {
+        for v in [-2.0, 0.5, 3.0, 8.0] {
+            check_shape(EasingType::Exponential(v));
+        }
+        // the same as a square at 0
+        let [into, ..] = all_directions(EasingType::Exponential(0.0));
+        assert!(close(into.apply(0.5), 0.25));
+        // and flat at the start, whatever the base
+        let [into, ..] = all_directions(EasingType::Exponential(3.0));
+        assert!(into.apply(0.001) < 0.001);
+    }
+
+    #[test]
+    fn cubic_bezier() {
+        check_shape(EasingType::CubicBezier(0.42, 0.0, 0.58, 1.0));
+        check_shape(EasingType::CubicBezier(0.0, 0.0, 1.0, 1.0));
+        check_shape(EasingType::CubicBezier(0.9, 0.0, 0.1, 1.0));
+        // with the control points on the diagonal, it's a straight line
+        let linear = Easing::cubic_bezier(0.25, 0.25, 0.75, 0.75);
+        for t in [0.1, 0.4, 0.7] {
+            assert!(close(linear.apply(t), t));
+        }
+        // css `ease`, against values from a browser
+        let ease = Easing::cubic_bezier(0.25, 0.1, 0.25, 1.0);
+        assert!((ease.apply(0.25) - 0.4094).abs() < 1e-3);
+        assert!((ease.apply(0.5) - 0.8024).abs() < 1e-3);
+        // x is solved even where the curve is flat in it
+        let steep = EasingType::CubicBezier(1.0, 0.0, 1.0, 0.0);
+        let s = bezier_parameter(1.0, 1.0, 0.9);
+        assert!(close(bezier(1.0, 1.0, s), 0.9));
+        assert!(steep.sample(0.9) < 0.2);
+        // and anything that would make x go backwards is clamped
+        check_shape(EasingType::CubicBezier(-1.0, 0.0, 2.0, 1.0));
+    }
+
+    #[test]
+    fn cubic_bezier_overshoots() {
+        let [into, out, in_out] = all_directions(EasingType::CubicBezier(0.5, -0.5, 0.5, 1.5));
+        for easing in [&into, &out, &in_out] {
+            assert!(close(easing.apply(0.0), 0.0), "{easing:?} at 0");
+            assert!(close(easing.apply(1.0), 1.0), "{easing:?} at 1");
+        }
+        assert!(into.apply(0.1) < 0.0);
+        assert!(into.apply(0.9) > 1.0);
+    }
+
+    #[test]
+    fn elastic() {
+        let [into, out, in_out] = all_directions(EasingType::Elastic);
+        for easing in [&into, &out, &in_out] {
+            assert_eq!(easing.apply(0.0), 0.0, "{easing:?} at 0");
+            assert_eq!(easing.apply(1.0), 1.0, "{easing:?} at 1");
+        }
+        // barely moves at the start, then swings past the end
+        assert!(into.apply(0.3).abs() < 0.02);
+        assert!(close(out.apply(0.1), 1.25));
+        assert!(close(in_out.apply(0.5), 0.5));
+    }
+
+    #[test]
+    fn bounce() {
+        let [into, out, in_out] = all_directions(EasingType::Bounce);
+        for easing in [&into, &out, &in_out] {
+            assert!(close(easing.apply(0.0), 0.0), "{easing:?} at 0");
+            assert!(close(easing.apply(1.0), 1.0), "{easing:?} at 1");
+            for i in 0..=100 {
+                let eased = easing.apply(i as f32 / 100.0);
+                assert!(
+                    (-1e-5..=1.0 + 1e-5).contains(&eased),
+                    "{easing:?} leaves 0-1"
+                );
+            }
+        }
+        // lands at the first bounce
+        assert!(close(out.apply(1.0 / 2.75), 1.0));
+        assert!(out.apply(1.5 / 2.75) < 1.0);
+    }
+
+    #[test]
+    fn back() {
+        let [into, out, in_out] = all_directions(EasingType::Back(1.70158));
+        for easing in [&into, &out, &in_out] {
+            assert!(close(easing.apply(0.0), 0.0), "{easing:?} at 0");
+            assert!(close(easing.apply(1.0), 1.0), "{easing:?} at 1");
+        }
+        // pulls back by about a tenth
+        let lowest = (0..=100)
+            .map(|i| into.apply(i as f32 / 100.0))
+            .fold(0.0f32, f32::min);
+        assert!((-0.11..-0.09).contains(&lowest));
+        assert!(out.apply(0.7) > 1.0);
+        // no overshoot is a cube
+        let [into, ..] = all_directions(EasingType::Back(0.0));
+        assert!(close(into.apply(0.5), 0.125));
+    }
+
+    #[test]
+    fn circular() {
+        check_shape(EasingType::Circular);
+        let [into, out, _] = all_directions(EasingType::Circular);
+        assert!(close(into.apply(0.6), 0.2));
+        assert!(close(out.apply(0.4), 0.8));
+    }
+
+    #[test]
+    fn steps() {
+        check_shape(EasingType::Steps(4));
+        let [into, out, in_out] = all_directions(EasingType::Steps(4));
+        assert_eq!(into.apply(0.2), 0.0);
+        assert_eq!(into.apply(0.3), 0.25);
+        assert_eq!(into.apply(0.99), 0.75);
+        // out jumps at the start of each step instead
+        assert_eq!(out.apply(0.01), 0.25);
+        assert_eq!(out.apply(0.3), 0.5);
+        assert_eq!(in_out.apply(0.3), 0.25);
+        // no steps at all is taken as one
+        let [into, ..] = all_directions(EasingType::Steps(0));
+        assert_eq!(into.apply(0.5), 0.0);
+        assert_eq!(into.apply(1.0), 1.0);
+    }
+
+    #[test]
+    fn custom() {
+        check_shape(EasingType::Custom(Arc::new(|t| t * t * t * t)));
+        let [into, out, in_out] = all_directions(EasingType::Custom(Arc::new(|t| t * t)));
+        assert!(close(into.apply(0.5), 0.25));
+        assert!(close(out.apply(0.5), 0.75));
+        assert!(close(in_out.apply(0.25), 0.125));
+    }
+
+    #[test]
+    fn in_out_is_symmetric() {
+        for etype in [
+            EasingType::Sine,
+            EasingType::Power(2.5),
+            EasingType::Exponential(4.0),
+            EasingType::CubicBezier(0.3, 0.1, 0.7, 0.2),
+            EasingType::Elastic,
+            EasingType::Bounce,
+            EasingType::Back(1.70158),
+            EasingType::Circular,
+        ] {
+            let [.., in_out] = all_directions(etype);
+            for t in [0.1, 0.3, 0.45] {
+                assert!(close(in_out.apply(t), 1.0 - in_out.apply(1.0 - t)));
+            }
+        }
+    }
+
+    #[test]
+    fn empty_sequence_samples_default() {
+        let sequence = KeyframeSequence::<f32>::new();
+        assert_eq!(sequence.sample(1.0), 0.0);
+    }
+
+    #[test]
+    fn holds_outside_keys() {
+        let sequence = KeyframeSequence::<f32>::new()
+            .key(1.0, 10.0, Easing::linear())
+            .key(3.0, 30.0, Easing::linear());
+        assert_eq!(sequence.sample(-5.0), 10.0);
+        assert_eq!(sequence.sample(1.0), 10.0);
+        assert_eq!(sequence.sample(3.0), 30.0);
+        assert_eq!(sequence.sample(100.0), 30.0);
+
+        let single = KeyframeSequence::<f32>::new().key(2.0, 5.0, Easing::linear());
+        assert_eq!(single.sample(0.0), 5.0);
+        assert_eq!(single.sample(4.0), 5.0);
+    }
+
+    #[test]
+    fn eases_with_the_earlier_key() {
+        let sequence = KeyframeSequence::<f32>::new()
+            .key(
+                0.0,
+                0.0,
+                Easing::new(EasingType::Power(2.0), EasingDirection::In),
+            )
+            .key(2.0, 4.0, Easing::hold())
+            .key(4.0, 8.0, Easing::linear());
+        assert!(close(sequence.sample(1.0), 1.0));
+        assert_eq!(sequence.sample(2.0), 4.0);
+        assert_eq!(sequence.sample(3.9), 4.0);
+        assert_eq!(sequence.sample(4.0), 8.0);
+    }
+
+    #[test]
+    fn keys_can_go_in_any_order() {
+        let mut sequence = KeyframeSequence::<f32>::new();
+        sequence.insert(2.0, 20.0, Easing::linear());
+        sequence.insert(0.0, 0.0, Easing::linear());
+        sequence.insert(1.0, 10.0, Easing::linear());
+        assert!(close(sequence.sample(0.5), 5.0));
+        assert!(close(sequence.sample(1.5), 15.0));
+    }
+
+    #[test]
+    fn keys_at_the_same_time_jump() {
+        let sequence = KeyframeSequence::<f32>::new()
+            .key(0.0, 0.0, Easing::linear())
+            .key(1.0, 1.0, Easing::linear())
+            .key(1.0, 5.0, Easing::linear())
+            .key(2.0, 6.0, Easing::linear());
+        assert!(close(sequence.sample(0.99), 0.99));
+        assert_eq!(sequence.sample(1.0), 5.0);
+        assert!(close(sequence.sample(1.5), 5.5));
+    }
+
+    fn ramp() -> KeyframeSequence<f32> {
+        KeyframeSequence::<f32>::new()
+            .key(1.0, 0.0, Easing::linear())
+            .key(
+                3.0,
+                2.0,
+                Easing::new(EasingType::Power(2.0), EasingDirection::In),
+            )
+            .key(5.0, 4.0, Easing::linear())
+    }
+
+    #[test]
+    fn loops() {
+        let sequence = ramp().extrapolate(Extrapolation::Loop);
+        for t in [1.5, 2.0, 3.5, 4.9] {
+            assert!(
+                close(sequence.sample(t + 4.0), sequence.sample(t)),
+                "at {t}"
+            );
+            assert!(
+                close(sequence.sample(t + 40.0), sequence.sample(t)),
+                "at {t}"
+            );
+            assert!(
+                close(sequence.sample(t - 8.0), sequence.sample(t)),
+                "at {t}"
+            );
+        }
+        assert_eq!(sequence.sample(5.0), 0.0);
+    }
+
+    #[test]
+    fn ping_pongs() {
+        let sequence = ramp().extrapolate(Extrapolation::PingPong);
+        for t in [1.5, 2.0, 3.5, 4.9] {
+            // mirrored about the last key, then back again
+            assert!(
+                close(sequence.sample(10.0 - t), sequence.sample(t)),
+                "at {t}"
+            );
+            assert!(
+                close(sequence.sample(t + 8.0), sequence.sample(t)),
+                "at {t}"
+            );
+            // and about the first one going backwards
+            assert!(
+                close(sequence.sample(2.0 - t), sequence.sample(t)),
+                "at {t}"
+            );
+        }
+        assert_eq!(sequence.sample(5.0), 4.0);
+        assert_eq!(sequence.sample(9.0), 0.0);
+    }
+
+    #[test]
+    fn loops_with_offset() {
+        let sequence = ramp().extrapolate(Extrapolation::LoopWithOffset);
+        for t in [1.5, 2.0, 3.5, 4.9] {
+            assert!(
+                close(sequence.sample(t + 4.0), sequence.sample(t) + 4.0),
+                "at {t}"
+            );
+            assert!(
+                close(sequence.sample(t + 12.0), sequence.sample(t) + 12.0),
+                "at {t}"
+            );
+            assert!(
+                close(sequence.sample(t - 4.0), sequence.sample(t) - 4.0),
+                "at {t}"
+            );
+        }
+        // no jump where one time round meets the next
+        assert!(close(sequence.sample(5.0), 4.0));
+        assert!((sequence.sample(4.999) - 4.0).abs() < 0.01);
+        assert!((sequence.sample(5.001) - 4.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn extrapolates_linearly() {
+        let sequence = ramp().extrapolate(Extrapolation::Linear);
+        assert!(close(sequence.sample(7.0), 6.0));
+        assert!(close(sequence.sample(-1.0), -2.0));
+        // each side is only its own
+        let sequence = ramp().after(Extrapolation::Linear);
+        assert_eq!(sequence.sample(-1.0), 0.0);
+        assert!(close(sequence.sample(6.0), 5.0));
+    }
+
+    #[test]
+    fn single_keys_always_hold() {
+        for extrapolation in [
+            Extrapolation::Loop,
+            Extrapolation::PingPong,
+            Extrapolation::LoopWithOffset,
+            Extrapolation::Linear,
+        ] {
+            let sequence = KeyframeSequence::<f32>::new()
+                .key(1.0, 3.0, Easing::linear())
+                .extrapolate(extrapolation);
+            assert_eq!(sequence.sample(-2.0), 3.0, "{extrapolation:?}");
+            assert_eq!(sequence.sample(5.0), 3.0, "{extrapolation:?}");
+        }
+    }
+
+    #[test]
+    fn scales_and_offsets_time() {
+        // keyed over one unit, stretched to a four beat bar starting on beat 2
+        let sequence = KeyframeSequence::<f32>::new()
+            .key(0.0, 0.0, Easing::linear())
+            .key(1.0, 1.0, Easing::linear())
+            .time_offset(2.0)
+            .time_scale(4.0)
+            .after(Extrapolation::Loop);
+        assert_eq!(sequence.sample(0.0), 0.0);
+        assert!(close(sequence.sample(3.0), 0.25));
+        assert!(close(sequence.sample(5.0), 0.75));
+        assert!(close(sequence.sample(7.0), 0.25));
+
+        let backwards = KeyframeSequence::<f32>::new()
+            .key(0.0, 0.0, Easing::linear())
+            .key(1.0, 1.0, Easing::linear())
+            .time_scale(-2.0);
+        assert!(close(backwards.sample(-1.0), 0.5));
+    }
+
+    fn same_rotation(a: Quat, b: Quat) -> bool {
+        a.angle_between(b) < 1e-3
+    }
+
+    #[test]
+    fn rotations_turn_the_short_way() {
+        let quarter = Quat::from_rotation_z(-PI * 0.5);
+        let three_quarters = Quat::from_rotation_z(PI * 1.5);
+        for to in [three_quarters, -three_quarters, quarter] {
+            let half = Quat::IDENTITY.tween(&to, 0.5);
+            assert!(
+                same_rotation(half, Quat::from_rotation_z(-PI * 0.25)),
+                "{to:?}"
+            );
+        }
+        // overshooting keeps going the same way
+        let past = Quat::IDENTITY.tween(&Quat::from_rotation_z(PI * 0.5), 1.5);
+        assert!(same_rotation(past, Quat::from_rotation_z(PI * 0.75)));
+    }
+
+    #[test]
+    fn rotations_loop_with_offset() {
+        let spin = KeyframeSequence::new()
+            .key(0.0, Quat::IDENTITY, Easing::linear())
+            .key(1.0, Quat::from_rotation_y(PI * 0.5), Easing::linear())
+            .after(Extrapolation::LoopWithOffset);
+        assert!(same_rotation(
+            spin.sample(2.5),
+            Quat::from_rotation_y(PI * 1.25)
+        ));
+        assert!(same_rotation(spin.sample(4.0), Quat::IDENTITY));
+    }
+
+    // how much it changes just before and just after `t`
+    fn slopes<T: Default + Tween>(
+        sequence: &KeyframeSequence<T>,
+        t: f32,
+        distance: impl Fn(&T, &T) -> f32,
+    ) -> (f32, f32) {
+        let h = 1e-2;
+        let at = sequence.sample(t);
+        (
+            distance(&sequence.sample(t - h), &at),
+            distance(&at, &sequence.sample(t + h)),
+        )
+    }
+
+    #[test]
+    fn smooth_curves_through_keys() {
+        let keys = [(0.0, 0.0), (1.0, 1.0), (2.0, 0.0), (3.0, 2.0)];
+        let [straight, smooth] = [false, true].map(|smooth| {
+            let mut sequence = KeyframeSequence::<f32>::new();
+            for (time, value) in keys {
+                sequence.insert(time, value, Easing::linear());
+            }
+            match smooth {
+                true => sequence.smooth(),
+                false => sequence,
+            }
+        });
+        for (time, value) in keys {
+            assert!(close(smooth.sample(time), value), "at {time}");
+        }
+        // no corner at the keys, unlike straight lines
+        let (before, after) = slopes(&smooth, 1.0, |a, b| b - a);
+        assert!((before - after).abs() < 1e-3);
+        let (before, after) = slopes(&straight, 1.0, |a, b| b - a);
+        assert!((before - after).abs() > 1e-3);
+    }
+
+    #[test]
+    fn squad_curves_through_keys() {
+        let keys = [
+            Quat::IDENTITY,
+            Quat::from_rotation_x(1.0),
+            Quat::from_rotation_x(1.0) * Quat::from_rotation_y(1.2),
+            Quat::from_rotation_z(0.5),
+        ];
+        let mut sequence = KeyframeSequence::new();
+        for (time, key) in keys.iter().enumerate() {
+            sequence.insert(time as f32, *key, Easing::linear());
+        }
+        let sequence = sequence.smooth();
+        for (time, key) in keys.iter().enumerate() {
+            assert!(
+                same_rotation(sequence.sample(time as f32), *key),
+                "at {time}"
+            );
+        }
+        let (before, after) = slopes(&sequence, 2.0, |a, b| a.angle_between(*b));
+        assert!((before - after).abs() < 1e-3, "{before} {after}");
+    }
+
+    fn tempo_map() -> TempoMap {
+        // 120bpm with the first beat half a second in, and a bar of 3/4 from bar 2
+        let mut tempo = TempoMap::new(120.0, 0.5);
+        tempo
+            .set_meter(
+                2,
+                Meter {
+                    beats_per_bar: 3,
+                    beat_unit: 4,
+                },
+            )
+            .unwrap();
+        tempo
+    }
+
+    #[test]
+    fn keys_in_the_music() {
+        let tempo = tempo_map();
+        let sequence = KeyframeSequence::<f32>::new()
+            .key_at(&tempo, TimePoint::Bar(1, 0.0), 0.0, Easing::linear())
+            .key_at(
+                &tempo,
+                TimePoint::parse("3:1:240").unwrap(),
+                1.0,
+                Easing::linear(),
+            )
+            .key_at(&tempo, TimePoint::Seconds(8.0), 2.0, Easing::linear())
+            .key_at(&tempo, TimePoint::Beats(16.5), 3.0, Easing::linear());
+        // bar 1 is beat 4, and bar 3 beat 8 + 3
+        assert_eq!(sequence.sample(4.0), 0.0);
+        assert_eq!(sequence.sample(12.5), 1.0);
+        // 8 seconds is 7.5 seconds after beat 0
+        assert_eq!(sequence.sample(15.0), 2.0);
+        assert_eq!(sequence.sample(16.5), 3.0);
+        assert!(close(sequence.sample(8.25), 0.5));
+    }
+
+    #[test]
+    fn retiming_follows_the_music() {
+        let mut sequence = KeyframeSequence::<f32>::new()
+            .key_at(&tempo_map(), TimePoint::Bar(4, 0.0), 0.0, Easing::linear())
+            .key_at(&tempo_map(), TimePoint::Seconds(9.5), 1.0, Easing::linear())
+            .key(20.0, 2.0, Easing::linear());
 
-        first
-            .1
-            .clone()
-            .lerp(second.1.clone(), first.2.apply(lerp_factor))
+        // the track is moved later and slowed down, and the meter stays 4/4 throughout
+        let tempo = TempoMap::new(60.0, 2.0);
+        sequence.retime(&tempo);
+        // bar 4 is now on beat 16, and 9.5 seconds is beat 7.5, so they swap places. plain keys
+        // don't move
+        assert_eq!(sequence.sample(7.5), 1.0);
+        assert!(close(sequence.sample(11.75), 0.5));
+        assert_eq!(sequence.sample(16.0), 0.0);
+        assert!(close(sequence.sample(18.0), 1.0));
+        // still the same moment of the track
+        assert!(close(tempo.seconds(7.5) as f32, 9.5));
     }
 }
@@ -68,7 +68,6 @@ impl Mesh {
         let position_accessor = &gltf.accessors[attributes
             .position
             .ok_or(anyhow!("no position buffer specified"))?];
-        dbg!(&position_accessor.accessor_type);
         if let AccessorType::Vec3 = position_accessor.accessor_type {
         } else {
             Err(anyhow!("expected position buffer type to be vec3"))?;
@@ -134,7 +133,7 @@ impl Mesh {
         Mesh::new(
             allocator,
             vertices,
-            indices.into_iter().map(|i| *i as u32),
+            indices.iter().map(|i| *i as u32),
             image,
             sampler,
         )
@@ -245,9 +244,9 @@ impl Mesh {
         pipeline: Arc<GraphicsPipeline>,
         transform: Mat4,
     ) -> Result<()> {
-        self.bind(render_commands, &pipeline);
-        self.rebind_transform(render_commands, pipeline, allocator, transform);
-        self.add_draw_command(render_commands);
+        self.bind(render_commands, &pipeline)?;
+        self.rebind_transform(render_commands, pipeline, allocator, transform)?;
+        self.add_draw_command(render_commands)?;
         Ok(())
     }
     pub fn draw_prebound<L, A: CommandBufferAllocator>(
@@ -257,8 +256,8 @@ impl Mesh {
         pipeline: Arc<GraphicsPipeline>,
         transform: Mat4,
     ) -> Result<()> {
-        self.rebind_transform(render_commands, pipeline, allocator, transform);
-        self.add_draw_command(render_commands);
+        self.rebind_transform(render_commands, pipeline, allocator, transform)?;
+        self.add_draw_command(render_commands)?;
         Ok(())
     }
 }
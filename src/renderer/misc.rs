@@ -1,10 +1,6 @@
-use std::{
-    borrow::Cow,
-    time::{Duration, Instant},
-};
+use std::time::{Duration, Instant};
 
 use glam::vec3;
-use rand::{thread_rng, Rng};
 use rodio::Sink;
 
 use crate::vertex;
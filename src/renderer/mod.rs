@@ -1,9 +1,22 @@
 pub mod app;
+pub mod clock;
 pub mod color;
+pub mod downsample;
+pub mod encoder;
 pub mod ext;
+pub mod keyframe;
 pub mod mesh;
 pub mod misc;
+pub mod output;
+pub mod rawvideo;
+pub mod recorder;
+pub mod sink;
 pub mod stopwatch;
+pub mod target;
+pub mod tempo;
 pub mod termbuf;
 pub mod texture;
+pub mod timeline;
+pub mod track;
 pub mod vertex;
+pub mod window;
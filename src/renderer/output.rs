@@ -0,0 +1,240 @@
+use std::sync::OnceLock;
+
+use anyhow::{bail, Result};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use super::sink::Frame;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Encoding {
+    /// Values are already what should end up on screen. Textures are loaded without
+    /// linearisation, so this is what every existing shader assumes.
+    #[default]
+    Display,
+    /// Values are linear light, and get the sRGB transfer function applied.
+    Srgb,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Tonemap {
+    /// Anything outside 0-1 is clipped.
+    #[default]
+    Clamp,
+    /// Extended Reinhard, reaching white at 4.0.
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve.
+    Aces,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Dither {
+    #[default]
+    None,
+    /// 8x8 Bayer matrix.
+    Ordered,
+    /// 64x64 void-and-cluster blue noise.
+    BlueNoise,
+}
+
+/// How the rendered `f32`s are turned into the integers that get written out.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct OutputTransform {
+    pub encoding: Encoding,
+    pub tonemap: Tonemap,
+    pub dither: Dither,
+}
+impl OutputTransform {
+    /// Overrides a single setting by name, as given on the command line.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match (key, value) {
+            ("encoding", "display") => self.encoding = Encoding::Display,
+            ("encoding", "srgb") => self.encoding = Encoding::Srgb,
+            ("tonemap", "clamp") => self.tonemap = Tonemap::Clamp,
+            ("tonemap", "reinhard") => self.tonemap = Tonemap::Reinhard,
+            ("tonemap", "aces") => self.tonemap = Tonemap::Aces,
+            ("dither", "none") => self.dither = Dither::None,
+            ("dither", "ordered") => self.dither = Dither::Ordered,
+            ("dither", "blue-noise") => self.dither = Dither::BlueNoise,
+            _ => bail!("invalid {key} `{value}`"),
+        }
+        Ok(())
+    }
+
+    /// Maps one colour channel into 0-1.
+    pub fn map(&self, v: f32) -> f32 {
+        // negative colours have no meaning on screen, and nan would end up as anything
+        let v = if v.is_nan() { 0.0 } else { v.max(0.0) };
+        let v = match self.tonemap {
+            Tonemap::Clamp => v,
+            Tonemap::Reinhard => {
+                const WHITE: f32 = 4.0;
+                v * (1.0 + v / (WHITE * WHITE)) / (1.0 + v)
+            }
+            Tonemap::Aces => (v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14),
+        }
+        .min(1.0);
+        match self.encoding {
+            Encoding::Display => v,
+            Encoding::Srgb => linear_to_srgb(v),
+        }
+    }
+
+    /// The value added before truncating to an integer, in 0-1. 0.5 rounds to nearest.
+    fn threshold(&self, x: usize, y: usize) -> f32 {
+        match self.dither {
+            Dither::None => 0.5,
+            Dither::Ordered => (BAYER_8X8[y % 8][x % 8] as f32 + 0.5) / 64.0,
+            Dither::BlueNoise => {
+                blue_noise()[(x % BLUE_NOISE_SIZE) + (y % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE]
+            }
+        }
+    }
+
+    /// Converts a frame to rgba8. Alpha is only ever clamped and rounded.
+    pub fn apply(&self, frame: &Frame, out: &mut [u8]) {
+        let width = frame.width as usize;
+        for (i, (dst, src)) in out
+            .chunks_exact_mut(4)
+            .zip(frame.pixels.chunks_exact(4))
+            .enumerate()
+        {
+            let threshold = self.threshold(i % width, i / width);
+            for channel in 0..3 {
+                dst[channel] = (self.map(src[channel]) * 255.0 + threshold).clamp(0.0, 255.0) as u8;
+            }
+            dst[3] = (src[3].clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+    }
+
+    /// Converts a frame to rgba16. 16 bits is enough that dithering isn't needed.
+    pub fn apply_16(&self, frame: &Frame, out: &mut [u16]) {
+        for (dst, src) in out.chunks_exact_mut(4).zip(frame.pixels.chunks_exact(4)) {
+            for channel in 0..3 {
+                dst[channel] = (self.map(src[channel]) * 65535.0).round() as u16;
+            }
+            dst[3] = (src[3].clamp(0.0, 1.0) * 65535.0).round() as u16;
+        }
+    }
+}
+
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[rustfmt::skip]
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [ 0, 32,  8, 40,  2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44,  4, 36, 14, 46,  6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [ 3, 35, 11, 43,  1, 33,  9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47,  7, 39, 13, 45,  5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// Thresholds in 0-1, generated once with Ulichney's void-and-cluster method.
+fn blue_noise() -> &'static [f32] {
+    static BLUE_NOISE: OnceLock<Vec<f32>> = OnceLock::new();
+    BLUE_NOISE.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE, 1.5))
+}
+
+fn void_and_cluster(size: usize, sigma: f32) -> Vec<f32> {
+    let count = size * size;
+
+    // gaussian falloff, wrapping around the edges so the texture tiles
+    let kernel = (0..count)
+        .map(|i| {
+            let wrap = |d: usize| d.min(size - d) as f32;
+            let (dx, dy) = (wrap(i % size), wrap(i / size));
+            (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect::<Vec<f32>>();
+
+    struct Pattern<'a> {
+        size: usize,
+        kernel: &'a [f32],
+        ones: Vec<bool>,
+        energy: Vec<f32>,
+    }
+    impl Pattern<'_> {
+        fn toggle(&mut self, i: usize) {
+            self.ones[i] = !self.ones[i];
+            let sign = if self.ones[i] { 1.0 } else { -1.0 };
+            let (x, y) = (i % self.size, i / self.size);
+            for (j, energy) in self.energy.iter_mut().enumerate() {
+                let dx = (j % self.size + self.size - x) % self.size;
+                let dy = (j / self.size + self.size - y) % self.size;
+                *energy += sign * self.kernel[dx + dy * self.size];
+            }
+        }
+        fn tightest_cluster(&self) -> usize {
+            (0..self.ones.len())
+                .filter(|&i| self.ones[i])
+                .max_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+                .unwrap()
+        }
+        fn largest_void(&self) -> usize {
+            (0..self.ones.len())
+                .filter(|&i| !self.ones[i])
+                .min_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+                .unwrap()
+        }
+    }
+
+    // initial binary pattern: a tenth of the pixels set at random, then shuffled until the ones
+    // are as evenly spread out as they can be
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let mut pattern = Pattern {
+        size,
+        kernel: &kernel,
+        ones: vec![false; count],
+        energy: vec![0.0; count],
+    };
+    let initial_ones = count / 10;
+    while pattern.ones.iter().filter(|&&one| one).count() < initial_ones {
+        let i = rng.gen_range(0..count);
+        if !pattern.ones[i] {
+            pattern.toggle(i);
+        }
+    }
+    loop {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster);
+        let void = pattern.largest_void();
+        if void == cluster {
+            pattern.toggle(cluster);
+            break;
+        }
+        pattern.toggle(void);
+    }
+
+    let mut rank = vec![0usize; count];
+
+    // phase 1: rank the initial ones by removing them, tightest cluster first
+    let prototype = (pattern.ones.clone(), pattern.energy.clone());
+    for r in (0..initial_ones).rev() {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster);
+        rank[cluster] = r;
+    }
+
+    // phases 2 and 3: fill in the rest, largest void first
+    (pattern.ones, pattern.energy) = prototype;
+    for r in initial_ones..count {
+        let void = pattern.largest_void();
+        pattern.toggle(void);
+        rank[void] = r;
+    }
+
+    rank.into_iter()
+        .map(|r| (r as f32 + 0.5) / count as f32)
+        .collect()
+}
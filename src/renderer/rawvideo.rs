@@ -0,0 +1,543 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+};
+
+use anyhow::{bail, Result};
+
+use super::{
+    clock::FrameRate,
+    output::OutputTransform,
+    sink::{Frame, FrameSink},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Chroma {
+    /// Chroma at half resolution in both directions, what almost everything expects.
+    C420,
+    /// Full resolution chroma.
+    C444,
+}
+
+fn luma(rgb: [f32; 3]) -> f32 {
+    0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+}
+
+fn to_u8(v: f32) -> u8 {
+    v.round().clamp(0.0, 255.0) as u8
+}
+
+/// Splits an rgba8 image into limited range BT.709 Y'CbCr planes, appending the Y, then Cb, then
+/// Cr planes to `out`. With [`Chroma::C420`], each chroma sample is the average of a 2x2 block.
+pub fn rgba_to_yuv(rgba: &[u8], width: usize, height: usize, chroma: Chroma, out: &mut Vec<u8>) {
+    let pixel = |x: usize, y: usize| {
+        let i = (x + y * width) * 4;
+        [
+            rgba[i] as f32 / 255.0,
+            rgba[i + 1] as f32 / 255.0,
+            rgba[i + 2] as f32 / 255.0,
+        ]
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            out.push(to_u8(16.0 + 219.0 * luma(pixel(x, y))));
+        }
+    }
+
+    let (chroma_width, chroma_height, block) = match chroma {
+        Chroma::C420 => (width.div_ceil(2), height.div_ceil(2), 2),
+        Chroma::C444 => (width, height, 1),
+    };
+    let mut cr = Vec::with_capacity(chroma_width * chroma_height);
+    for cy in 0..chroma_height {
+        for cx in 0..chroma_width {
+            let mut sum = [0.0; 3];
+            let mut count = 0.0;
+            for y in cy * block..((cy + 1) * block).min(height) {
+                for x in cx * block..((cx + 1) * block).min(width) {
+                    let rgb = pixel(x, y);
+                    sum = [sum[0] + rgb[0], sum[1] + rgb[1], sum[2] + rgb[2]];
+                    count += 1.0;
+                }
+            }
+            let rgb = sum.map(|v| v / count);
+            let y = luma(rgb);
+            out.push(to_u8(128.0 + 224.0 * (rgb[2] - y) / 1.8556));
+            cr.push(to_u8(128.0 + 224.0 * (rgb[0] - y) / 1.5748));
+        }
+    }
+    out.extend(cr);
+}
+
+/// Writes YUV4MPEG2, which is raw planar yuv with a tiny text header. Needs no encoder, and
+/// ffmpeg, mpv and most other video tools read it directly.
+pub struct Y4mSink<W: Write = File> {
+    file: BufWriter<W>,
+    path: String,
+    chroma: Chroma,
+    transform: OutputTransform,
+    unorm_buffer: Vec<u8>,
+    yuv_buffer: Vec<u8>,
+}
+impl Y4mSink {
+    pub fn new(
+        path: impl Into<String>,
+        width: u32,
+        height: u32,
+        frame_rate: FrameRate,
+        chroma: Chroma,
+        transform: OutputTransform,
+    ) -> Result<Self> {
+        let path = path.into();
+        let file = File::create(&path)?;
+        Self::to_writer(file, path, width, height, frame_rate, chroma, transform)
+    }
+}
+impl<W: Write> Y4mSink<W> {
+    /// Writes the stream into `writer` instead of a file. `path` is only used in messages.
+    pub fn to_writer(
+        writer: W,
+        path: impl Into<String>,
+        width: u32,
+        height: u32,
+        frame_rate: FrameRate,
+        chroma: Chroma,
+        transform: OutputTransform,
+    ) -> Result<Self> {
+        let path = path.into();
+        let mut file = BufWriter::new(writer);
+        let FrameRate {
+            numerator: rate,
+            denominator: scale,
+        } = frame_rate;
+        let colorspace = match chroma {
+            Chroma::C420 => "C420jpeg",
+            Chroma::C444 => "C444",
+        };
+        writeln!(
+            file,
+            "YUV4MPEG2 W{width} H{height} F{rate}:{scale} Ip A1:1 {colorspace} XCOLORRANGE=LIMITED"
+        )?;
+        Ok(Self {
+            file,
+            path,
+            chroma,
+            transform,
+            unorm_buffer: vec![0; width as usize * height as usize * 4],
+            yuv_buffer: Vec::new(),
+        })
+    }
+}
+impl<W: Write + Send> FrameSink for Y4mSink<W> {
+    fn write(&mut self, frame: &Frame) -> Result<()> {
+        self.transform.apply(frame, &mut self.unorm_buffer);
+        self.yuv_buffer.clear();
+        rgba_to_yuv(
+            &self.unorm_buffer,
+            frame.width as usize,
+            frame.height as usize,
+            self.chroma,
+            &mut self.yuv_buffer,
+        );
+        self.file.write_all(b"FRAME\n")?;
+        self.file.write_all(&self.yuv_buffer)?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>, audio: Option<(&str, f64)>) -> Result<()> {
+        self.file.flush()?;
+        if let Some((audio_file, audio_offset)) = audio {
+            println!(
+                "{} has no audio; it lines up with {audio_file} from {audio_offset}s",
+                self.path
+            );
+        }
+        Ok(())
+    }
+}
+
+// byte offsets of the fields patched once the frame count is known. the header layout is fixed,
+// so these never move
+const RIFF_SIZE_OFFSET: u64 = 4;
+const AVIH_TOTAL_FRAMES_OFFSET: u64 = 48;
+const STRH_LENGTH_OFFSET: u64 = 140;
+const MOVI_SIZE_OFFSET: u64 = 216;
+const MOVI_START: u64 = 220;
+
+/// Writes uncompressed 24 bit bgr AVI. Plain RIFF AVI can't grow past 4GiB, so this is only meant
+/// for short clips.
+pub struct AviSink<W: Write + Seek = File> {
+    file: BufWriter<W>,
+    path: String,
+    width: usize,
+    height: usize,
+    transform: OutputTransform,
+    unorm_buffer: Vec<u8>,
+    bgr_buffer: Vec<u8>,
+    // (offset from the `movi` fourcc, length) of every frame chunk, for the `idx1` index
+    index: Vec<(u32, u32)>,
+    position: u64,
+}
+impl AviSink {
+    pub fn new(
+        path: impl Into<String>,
+        width: u32,
+        height: u32,
+        frame_rate: FrameRate,
+        transform: OutputTransform,
+    ) -> Result<Self> {
+        let path = path.into();
+        let file = File::create(&path)?;
+        Self::to_writer(file, path, width, height, frame_rate, transform)
+    }
+}
+impl<W: Write + Seek> AviSink<W> {
+    /// Writes the clip into `writer` instead of a file. `path` is only used in messages.
+    pub fn to_writer(
+        writer: W,
+        path: impl Into<String>,
+        width: u32,
+        height: u32,
+        frame_rate: FrameRate,
+        transform: OutputTransform,
+    ) -> Result<Self> {
+        let path = path.into();
+        let mut file = BufWriter::new(writer);
+        let FrameRate {
+            numerator: rate,
+            denominator: scale,
+        } = frame_rate;
+        let stride = (width as usize * 3).next_multiple_of(4);
+        let frame_size = (stride * height as usize) as u32;
+
+        let mut header = Vec::new();
+        let fourcc = |header: &mut Vec<u8>, code: &[u8; 4]| header.extend(code);
+        let dword = |header: &mut Vec<u8>, v: u32| header.extend(v.to_le_bytes());
+        let word = |header: &mut Vec<u8>, v: u16| header.extend(v.to_le_bytes());
+
+        fourcc(&mut header, b"RIFF");
+        dword(&mut header, 0);
+        fourcc(&mut header, b"AVI ");
+
+        fourcc(&mut header, b"LIST");
+        dword(&mut header, 192);
+        fourcc(&mut header, b"hdrl");
+
+        fourcc(&mut header, b"avih");
+        dword(&mut header, 56);
+        dword(
+            &mut header,
+            (1_000_000.0 * scale as f64 / rate as f64).round() as u32,
+        );
+        dword(
+            &mut header,
+            (frame_size as f64 * rate as f64 / scale as f64) as u32,
+        );
+        dword(&mut header, 0);
+        dword(&mut header, 0x10); // AVIF_HASINDEX
+        dword(&mut header, 0); // total frames, patched in finish
+        dword(&mut header, 0);
+        dword(&mut header, 1);
+        dword(&mut header, frame_size);
+        dword(&mut header, width);
+        dword(&mut header, height);
+        header.extend([0; 16]);
+
+        fourcc(&mut header, b"LIST");
+        dword(&mut header, 116);
+        fourcc(&mut header, b"strl");
+
+        fourcc(&mut header, b"strh");
+        dword(&mut header, 56);
+        fourcc(&mut header, b"vids");
+        fourcc(&mut header, b"DIB ");
+        dword(&mut header, 0);
+        word(&mut header, 0);
+        word(&mut header, 0);
+        dword(&mut header, 0);
+        dword(&mut header, scale);
+        dword(&mut header, rate);
+        dword(&mut header, 0);
+        dword(&mut header, 0); // length in frames, patched in finish
+        dword(&mut header, frame_size);
+        dword(&mut header, u32::MAX);
+        dword(&mut header, 0);
+        word(&mut header, 0);
+        word(&mut header, 0);
+        word(&mut header, width as u16);
+        word(&mut header, height as u16);
+
+        fourcc(&mut header, b"strf");
+        dword(&mut header, 40);
+        dword(&mut header, 40);
+        dword(&mut header, width);
+        // positive height means rows are stored bottom to top
+        dword(&mut header, height);
+        word(&mut header, 1);
+        word(&mut header, 24);
+        dword(&mut header, 0); // BI_RGB
+        dword(&mut header, frame_size);
+        header.extend([0; 16]);
+
+        fourcc(&mut header, b"LIST");
+        dword(&mut header, 0); // movi size, patched in finish
+        fourcc(&mut header, b"movi");
+
+        debug_assert_eq!(header.len() as u64, MOVI_START + 4);
+        file.write_all(&header)?;
+
+        Ok(Self {
+            file,
+            path,
+            width: width as usize,
+            height: height as usize,
+            transform,
+            unorm_buffer: vec![0; width as usize * height as usize * 4],
+            bgr_buffer: vec![0; frame_size as usize],
+            index: Vec::new(),
+            position: header.len() as u64,
+        })
+    }
+
+    fn patch(&mut self, offset: u64, value: u32) -> Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(&value.to_le_bytes())?;
+        Ok(())
+    }
+}
+impl<W: Write + Seek + Send> FrameSink for AviSink<W> {
+    fn write(&mut self, frame: &Frame) -> Result<()> {
+        let chunk_size = 8 + self.bgr_buffer.len() as u64;
+        // leave room for the index and the riff headers
+        let index_size = 16 * (self.index.len() as u64 + 1) + 8;
+        if self.position + chunk_size + index_size > u32::MAX as u64 {
+            bail!(
+                "{} would grow past the 4GiB avi limit; record longer clips as y4m",
+                self.path
+            );
+        }
+
+        self.transform.apply(frame, &mut self.unorm_buffer);
+        let stride = self.bgr_buffer.len() / self.height;
+        for y in 0..self.height {
+            let row = &mut self.bgr_buffer[(self.height - 1 - y) * stride..][..self.width * 3];
+            for (x, bgr) in row.chunks_exact_mut(3).enumerate() {
+                let i = (x + y * self.width) * 4;
+                bgr.copy_from_slice(&[
+                    self.unorm_buffer[i + 2],
+                    self.unorm_buffer[i + 1],
+                    self.unorm_buffer[i],
+                ]);
+            }
+        }
+
+        self.index.push((
+            (self.position - MOVI_START) as u32,
+            self.bgr_buffer.len() as u32,
+        ));
+        self.file.write_all(b"00db")?;
+        self.file
+            .write_all(&(self.bgr_buffer.len() as u32).to_le_bytes())?;
+        self.file.write_all(&self.bgr_buffer)?;
+        self.position += chunk_size;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>, audio: Option<(&str, f64)>) -> Result<()> {
+        let movi_end = self.position;
+        let frame_count = self.index.len() as u32;
+
+        self.file.write_all(b"idx1")?;
+        self.file
+            .write_all(&(self.index.len() as u32 * 16).to_le_bytes())?;
+        for (offset, length) in std::mem::take(&mut self.index).iter() {
+            self.file.write_all(b"00db")?;
+            self.file.write_all(&0x10u32.to_le_bytes())?; // AVIIF_KEYFRAME
+            self.file.write_all(&offset.to_le_bytes())?;
+            self.file.write_all(&length.to_le_bytes())?;
+            self.position += 16;
+        }
+        self.position += 8;
+
+        self.patch(RIFF_SIZE_OFFSET, (self.position - 8) as u32)?;
+        self.patch(AVIH_TOTAL_FRAMES_OFFSET, frame_count)?;
+        self.patch(STRH_LENGTH_OFFSET, frame_count)?;
+        self.patch(MOVI_SIZE_OFFSET, (movi_end - MOVI_SIZE_OFFSET - 4) as u32)?;
+        self.file.flush()?;
+
+        if let Some((audio_file, audio_offset)) = audio {
+            println!(
+                "{} has no audio; it lines up with {audio_file} from {audio_offset}s",
+                self.path
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Cursor},
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    /// Somewhere to write that can still be read once the sink is finished with it.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Cursor<Vec<u8>>>>);
+    impl Shared {
+        fn bytes(&self) -> Vec<u8> {
+            self.0.lock().unwrap().get_ref().clone()
+        }
+    }
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    impl Seek for Shared {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.0.lock().unwrap().seek(pos)
+        }
+    }
+
+    fn dword(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Writes a white frame and then a black one.
+    fn write_frames(sink: Box<dyn FrameSink>, width: u32, height: u32) {
+        let mut sink = sink;
+        for (index, value) in [1.0, 0.0].into_iter().enumerate() {
+            let pixels = vec![value; (width * height * 4) as usize];
+            sink.write(&Frame {
+                index,
+                width,
+                height,
+                pixels: &pixels,
+            })
+            .unwrap();
+        }
+        sink.finish(None).unwrap();
+    }
+
+    fn y4m(chroma: Chroma, width: u32, height: u32) -> Vec<u8> {
+        let output = Shared::default();
+        let sink = Y4mSink::to_writer(
+            output.clone(),
+            "test.y4m",
+            width,
+            height,
+            FrameRate::new(30000, 1001),
+            chroma,
+            OutputTransform::default(),
+        )
+        .unwrap();
+        write_frames(Box::new(sink), width, height);
+        output.bytes()
+    }
+
+    #[test]
+    fn y4m_420() {
+        let bytes = y4m(Chroma::C420, 3, 3);
+        let header = b"YUV4MPEG2 W3 H3 F30000:1001 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED\n";
+        assert!(bytes.starts_with(header));
+        // 3x3 luma, and chroma rounded up to 2x2
+        let frame_size = 6 + 9 + 4 + 4;
+        assert_eq!(bytes.len(), header.len() + 2 * frame_size);
+
+        let white = &bytes[header.len()..][..frame_size];
+        assert_eq!(&white[..6], b"FRAME\n");
+        assert_eq!(&white[6..15], &[235; 9]);
+        assert_eq!(&white[15..], &[128; 8]);
+        let black = &bytes[header.len() + frame_size..];
+        assert_eq!(&black[..6], b"FRAME\n");
+        assert_eq!(&black[6..15], &[16; 9]);
+        assert_eq!(&black[15..], &[128; 8]);
+    }
+
+    #[test]
+    fn y4m_444() {
+        let bytes = y4m(Chroma::C444, 3, 3);
+        let header = b"YUV4MPEG2 W3 H3 F30000:1001 Ip A1:1 C444 XCOLORRANGE=LIMITED\n";
+        assert!(bytes.starts_with(header));
+        let frame_size = 6 + 3 * 9;
+        assert_eq!(bytes.len(), header.len() + 2 * frame_size);
+        assert_eq!(&bytes[header.len() + frame_size..][..6], b"FRAME\n");
+    }
+
+    #[test]
+    fn avi_layout() {
+        let (width, height) = (3, 2);
+        let output = Shared::default();
+        let sink = AviSink::to_writer(
+            output.clone(),
+            "test.avi",
+            width,
+            height,
+            FrameRate::new(30, 1),
+            OutputTransform::default(),
+        )
+        .unwrap();
+        write_frames(Box::new(sink), width, height);
+        let bytes = output.bytes();
+
+        // rows are padded to 4 bytes
+        let frame_size = 12 * 2;
+        let chunk_size = 8 + frame_size;
+        let movi_end = MOVI_START as usize + 4 + 2 * chunk_size;
+        let file_size = movi_end + 8 + 2 * 16;
+        assert_eq!(bytes.len(), file_size);
+
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(
+            dword(&bytes, RIFF_SIZE_OFFSET as usize),
+            file_size as u32 - 8
+        );
+        assert_eq!(&bytes[8..12], b"AVI ");
+        // microseconds per frame, then both frame counts
+        assert_eq!(dword(&bytes, 32), 33333);
+        assert_eq!(dword(&bytes, AVIH_TOTAL_FRAMES_OFFSET as usize), 2);
+        assert_eq!(dword(&bytes, STRH_LENGTH_OFFSET as usize), 2);
+        // scale and rate
+        assert_eq!(dword(&bytes, 128), 1);
+        assert_eq!(dword(&bytes, 132), 30);
+        assert_eq!(dword(&bytes, 176), width);
+        assert_eq!(dword(&bytes, 180), height);
+
+        assert_eq!(&bytes[MOVI_SIZE_OFFSET as usize - 4..][..4], b"LIST");
+        assert_eq!(
+            dword(&bytes, MOVI_SIZE_OFFSET as usize),
+            (movi_end - MOVI_START as usize) as u32
+        );
+        assert_eq!(&bytes[MOVI_START as usize..][..4], b"movi");
+
+        for frame in 0..2 {
+            let chunk = &bytes[MOVI_START as usize + 4 + frame * chunk_size..][..chunk_size];
+            assert_eq!(&chunk[..4], b"00db");
+            assert_eq!(dword(chunk, 4), frame_size as u32);
+            let value = if frame == 0 { 255 } else { 0 };
+            for row in chunk[8..].chunks_exact(12) {
+                assert_eq!(&row[..9], &[value; 9]);
+                assert_eq!(&row[9..], &[0; 3]);
+            }
+        }
+
+        let index = &bytes[movi_end..];
+        assert_eq!(&index[..4], b"idx1");
+        assert_eq!(dword(index, 4), 32);
+        for (frame, entry) in index[8..].chunks_exact(16).enumerate() {
+            assert_eq!(&entry[..4], b"00db");
+            assert_eq!(dword(entry, 4), 0x10);
+            // offsets count from the `movi` fourcc
+            assert_eq!(dword(entry, 8), (4 + frame * chunk_size) as u32);
+            assert_eq!(dword(entry, 12), frame_size as u32);
+        }
+    }
+}
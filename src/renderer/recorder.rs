@@ -0,0 +1,430 @@
+use std::{
+    collections::VecDeque,
+    slice,
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TryRecvError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{anyhow, bail, Result};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
+        CopyImageToBufferInfo,
+    },
+    descriptor_set::{allocator::StandardDescriptorSetAllocator, PersistentDescriptorSet},
+    device::{DeviceOwned, Queue},
+    format::Format,
+    image::{Image, ImageCreateInfo, ImageType, ImageUsage},
+    memory::allocator::{AllocationCreateInfo, MemoryAllocator, MemoryTypeFilter},
+    render_pass::{Framebuffer, RenderPass},
+    sync::{future::FenceSignalFuture, GpuFuture},
+};
+
+use super::{
+    downsample::Downsampler,
+    sink::{Frame, FrameSink},
+    target::{create_framebuffers, RenderTarget},
+};
+
+/// One recording image, and the staging buffer it gets copied back into.
+struct Slot {
+    /// What frames are rendered into. Bigger than `image` when supersampling.
+    target: Arc<Image>,
+    /// The output sized image that gets copied back. The same as `target` without supersampling.
+    image: Arc<Image>,
+    /// `target` and `image`, bound for downsampling one into the other.
+    downsample: Option<Arc<PersistentDescriptorSet>>,
+    framebuffer: Arc<Framebuffer>,
+    staging_buffer: Subbuffer<[f32]>,
+    /// Set from when the copy into `staging_buffer` is submitted until the writer thread is done
+    /// reading it.
+    busy: bool,
+}
+
+type CopyFence = FenceSignalFuture<Box<dyn GpuFuture>>;
+/// The slot a frame is in, its index, and the staging buffer to read it from.
+type CopiedFrame = (usize, usize, Subbuffer<[f32]>);
+type Writer = JoinHandle<Result<Vec<Box<dyn FrameSink>>>>;
+
+/// The writer thread for one recording, and the channels to it.
+struct Take {
+    frames: SyncSender<CopiedFrame>,
+    written: Receiver<usize>,
+    writer: Writer,
+}
+
+/// A ring of offscreen images that frames are rendered into, plus everything needed to read them
+/// back and hand them to the [`FrameSink`]s. Every other [`RenderTarget`] gets frames from these
+/// images, and everything that ends up on the cpu goes through the recorder's sinks.
+///
+/// Frames are copied back and written out while the following ones are rendered: copies are
+/// submitted without waiting on them, and the conversion and writing happens on a separate thread.
+/// Rendering only blocks once every slot in the ring is still in use.
+///
+/// Frames are only read back between [`Self::start`] and [`Self::stop`]. The rest of the time, the
+/// images are just somewhere to render to.
+pub struct Recorder {
+    slots: Vec<Slot>,
+    current: usize,
+    downsampler: Option<Downsampler>,
+    command_buffer_allocator: StandardCommandBufferAllocator,
+    extent: [u32; 3],
+
+    /// Copies that have been submitted but not yet handed to the writer thread, oldest first, with
+    /// the slot and frame index they're for.
+    copies: VecDeque<(usize, usize, CopyFence)>,
+    take: Option<Take>,
+
+    frame_index: usize,
+}
+impl Recorder {
+    /// `extent` is the size of the recorded frames, which are rendered `supersample` times as large
+    /// in each direction. `depth` is how many frames can be in flight between being rendered and
+    /// being written.
+    pub fn new(
+        allocator: Arc<dyn MemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        render_pass: &Arc<RenderPass>,
+        format: Format,
+        extent: [u32; 3],
+        supersample: u32,
+        depth: usize,
+    ) -> Result<Self> {
+        if depth == 0 {
+            bail!("the readback depth has to be at least 1");
+        }
+        if supersample == 0 {
+            bail!("the supersampling factor has to be at least 1");
+        }
+
+        let [width, height, _] = extent;
+        let render_extent = [width * supersample, height * supersample, 1];
+        let properties = allocator.device().physical_device().properties();
+        let max_width = properties
+            .max_image_dimension2_d
+            .min(properties.max_framebuffer_width);
+        let max_height = properties
+            .max_image_dimension2_d
+            .min(properties.max_framebuffer_height);
+        if render_extent[0] > max_width || render_extent[1] > max_height {
+            bail!(
+                "can't render at {}x{}; this device can only go up to {max_width}x{max_height}",
+                render_extent[0],
+                render_extent[1],
+            );
+        }
+
+        let downsampler = (supersample > 1)
+            .then(|| {
+                Downsampler::new(
+                    allocator.device().clone(),
+                    descriptor_set_allocator,
+                    supersample,
+                )
+            })
+            .transpose()?;
+        let create_image = |extent, usage| {
+            Image::new(
+                allocator.clone(),
+                ImageCreateInfo {
+                    format,
+                    image_type: ImageType::Dim2d,
+                    usage,
+                    extent,
+                    ..Default::default()
+                },
+                AllocationCreateInfo::default(),
+            )
+        };
+
+        let slots = (0..depth)
+            .map(|_| -> Result<Slot> {
+                // create image for recorded output
+                let (target, image, downsample) = match &downsampler {
+                    None => {
+                        let image = create_image(
+                            extent,
+                            ImageUsage::TRANSFER_DST
+                                | ImageUsage::TRANSFER_SRC
+                                | ImageUsage::COLOR_ATTACHMENT,
+                        )?;
+                        (image.clone(), image, None)
+                    }
+                    Some(downsampler) => {
+                        let target = create_image(
+                            render_extent,
+                            ImageUsage::TRANSFER_SRC
+                                | ImageUsage::COLOR_ATTACHMENT
+                                | ImageUsage::STORAGE,
+                        )?;
+                        let image =
+                            create_image(extent, ImageUsage::TRANSFER_SRC | ImageUsage::STORAGE)?;
+                        let downsample = downsampler.bind(target.clone(), image.clone())?;
+                        (target, image, Some(downsample))
+                    }
+                };
+                // create framebuffer for recording image
+                let framebuffer =
+                    create_framebuffers(slice::from_ref(&target), render_pass, allocator.clone())?
+                        .remove(0);
+                // create staging buffer for saving image
+                let pixel_count = extent[0] as usize * extent[1] as usize * 4;
+                let staging_buffer = Buffer::from_iter(
+                    allocator.clone(),
+                    BufferCreateInfo {
+                        usage: BufferUsage::TRANSFER_DST,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                        ..Default::default()
+                    },
+                    (0..pixel_count).map(|_| 0.0f32),
+                )?;
+                Ok(Slot {
+                    target,
+                    image,
+                    downsample,
+                    framebuffer,
+                    staging_buffer,
+                    busy: false,
+                })
+            })
+            .collect::<Result<Vec<Slot>>>()?;
+
+        Ok(Self {
+            slots,
+            current: 0,
+            downsampler,
+            command_buffer_allocator: StandardCommandBufferAllocator::new(
+                allocator.device().clone(),
+                Default::default(),
+            ),
+            extent,
+            copies: VecDeque::new(),
+            take: None,
+            frame_index: 0,
+        })
+    }
+
+    /// Starts reading back every captured frame and writing it to `sinks`, numbering frames from
+    /// 0 again.
+    pub fn start(&mut self, sinks: Vec<Box<dyn FrameSink>>) -> Result<()> {
+        if self.take.is_some() {
+            bail!("already recording");
+        }
+        let (frames, frame_receiver) = mpsc::sync_channel::<CopiedFrame>(self.slots.len());
+        let (written_sender, written) = mpsc::channel();
+        let [width, height, _] = self.extent;
+        let writer = thread::spawn(move || {
+            let mut sinks = sinks;
+            for (slot, index, staging_buffer) in frame_receiver {
+                let pixels = staging_buffer.read()?;
+                let frame = Frame {
+                    index,
+                    width,
+                    height,
+                    pixels: &pixels,
+                };
+                for sink in sinks.iter_mut() {
+                    sink.write(&frame)?;
+                }
+                drop(pixels);
+                // the main thread only stops listening when it has given up on the recording
+                let _ = written_sender.send(slot);
+            }
+            Ok(sinks)
+        });
+
+        self.take = Some(Take {
+            frames,
+            written,
+            writer,
+        });
+        self.frame_index = 0;
+        Ok(())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.take.is_some()
+    }
+
+    /// The framebuffer the next frame should be rendered into. Blocks if its slot is still being
+    /// read back or written out.
+    pub fn framebuffer(&mut self) -> Result<Arc<Framebuffer>> {
+        // pass along whatever has finished in the meantime, so the writer thread is never idle
+        while self.hand_off(false)? {}
+        while self.receive(false)? {}
+
+        // slots are used round robin, so the current one is always the oldest
+        while self.slots[self.current].busy {
+            if self
+                .copies
+                .front()
+                .is_some_and(|&(slot, ..)| slot == self.current)
+            {
+                self.hand_off(true)?;
+            } else {
+                self.receive(true)?;
+            }
+        }
+        Ok(self.slots[self.current].framebuffer.clone())
+    }
+
+    /// The image behind [`Self::framebuffer`].
+    pub fn image(&self) -> Arc<Image> {
+        self.slots[self.current].target.clone()
+    }
+
+    /// The size frames are rendered at, supersampling included.
+    pub fn render_extent(&self) -> [u32; 3] {
+        self.slots[0].target.extent()
+    }
+
+    /// Queues a copy of the frame rendered into [`Self::framebuffer`] back to the host, downsampled
+    /// first if need be, to run once `rendered` is done. Doesn't block; the frame is written out on
+    /// the writer thread.
+    pub fn capture(
+        &mut self,
+        queue: &Arc<Queue>,
+        rendered: impl GpuFuture + 'static,
+    ) -> Result<()> {
+        if self.take.is_none() {
+            bail!("can't capture a frame without recording");
+        }
+        let slot = &mut self.slots[self.current];
+
+        let mut copy_buffer = AutoCommandBufferBuilder::primary(
+            &self.command_buffer_allocator,
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+        if let (Some(downsampler), Some(images)) = (&self.downsampler, &slot.downsample) {
+            let [width, height, _] = slot.image.extent();
+            downsampler.downsample(&mut copy_buffer, images.clone(), [width, height])?;
+        }
+        copy_buffer.copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+            slot.image.clone(),
+            slot.staging_buffer.clone(),
+        ))?;
+        let copied = rendered
+            .then_execute(queue.clone(), copy_buffer.build()?)?
+            .boxed()
+            .then_signal_fence_and_flush()?;
+
+        slot.busy = true;
+        self.copies
+            .push_back((self.current, self.frame_index, copied));
+        self.current = (self.current + 1) % self.slots.len();
+        self.frame_index += 1;
+        Ok(())
+    }
+
+    /// Waits for every frame to be written, then finishes every sink, passing along the audio to
+    /// go with the video if there is any. The recorder can be started again afterwards.
+    pub fn stop(&mut self, audio: Option<(&str, f64)>) -> Result<()> {
+        while self.hand_off(true)? {}
+        let Some(take) = self.take.take() else {
+            bail!("not recording");
+        };
+        // closing the channel lets the writer thread run out of frames and stop
+        drop(take.frames);
+        let sinks = match take.writer.join() {
+            Ok(sinks) => sinks?,
+            Err(_) => bail!("the frame writer thread panicked"),
+        };
+        for slot in self.slots.iter_mut() {
+            slot.busy = false;
+        }
+        for sink in sinks {
+            sink.finish(audio)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the oldest finished copy to the writer thread. Returns whether there was one.
+    fn hand_off(&mut self, block: bool) -> Result<bool> {
+        let Some((_, _, copied)) = self.copies.front() else {
+            return Ok(false);
+        };
+        if !block && !copied.is_signaled()? {
+            return Ok(false);
+        }
+        let (slot, index, copied) = self.copies.pop_front().unwrap();
+        // also releases the staging buffer, so the writer thread can read it
+        copied.wait(None)?;
+
+        let staging_buffer = self.slots[slot].staging_buffer.clone();
+        let take = self.take.as_ref().unwrap();
+        if take.frames.send((slot, index, staging_buffer)).is_err() {
+            return Err(self.abandon());
+        }
+        Ok(true)
+    }
+
+    /// Frees a slot the writer thread is done with. Returns whether there was one.
+    fn receive(&mut self, block: bool) -> Result<bool> {
+        let Some(take) = self.take.as_ref() else {
+            return Ok(false);
+        };
+        let slot = if block {
+            take.written.recv().map_err(|_| TryRecvError::Disconnected)
+        } else {
+            take.written.try_recv()
+        };
+        match slot {
+            Ok(slot) => {
+                self.slots[slot].busy = false;
+                Ok(true)
+            }
+            Err(TryRecvError::Empty) => Ok(false),
+            Err(TryRecvError::Disconnected) => Err(self.abandon()),
+        }
+    }
+
+    /// Gives up on the recording once the writer thread has stopped early, returning why it did.
+    /// The recorder can be started again afterwards.
+    fn abandon(&mut self) -> anyhow::Error {
+        self.copies.clear();
+        for slot in self.slots.iter_mut() {
+            slot.busy = false;
+        }
+        match self.take.take() {
+            Some(take) => take.writer_error(),
+            None => anyhow!("not recording"),
+        }
+    }
+}
+
+impl RenderTarget for Recorder {
+    /// Captures the frame if recording. Frames are drawn straight into the recorder, so there's
+    /// nothing to record beforehand.
+    fn submitted(&mut self, queue: &Arc<Queue>, rendered: Box<dyn GpuFuture>) -> Result<()> {
+        if self.is_recording() {
+            self.capture(queue, rendered)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, audio: Option<(&str, f64)>) -> Result<()> {
+        if self.is_recording() {
+            self.stop(audio)?;
+        }
+        Ok(())
+    }
+}
+
+impl Take {
+    /// Why the writer thread stopped early.
+    fn writer_error(self) -> anyhow::Error {
+        match self.writer.join() {
+            Ok(Err(error)) => error,
+            Err(_) => anyhow!("the frame writer thread panicked"),
+            Ok(Ok(_)) => anyhow!("the frame writer thread stopped early"),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    process::{Child, ChildStdin},
+};
+
+use anyhow::{anyhow, bail, Result};
+use image::{ImageBuffer, ImageFormat, Rgba, Rgba32FImage, RgbaImage};
+
+use super::{
+    clock::FrameRate, encoder::EncoderSettings, output::OutputTransform, rawvideo::Chroma,
+};
+
+/// A single rendered frame, straight out of the recording image's staging buffer.
+pub struct Frame<'a> {
+    pub index: usize,
+    pub width: u32,
+    pub height: u32,
+    /// Tightly packed rgba, in the same `f32`s the frame was rendered with.
+    pub pixels: &'a [f32],
+}
+
+/// Somewhere recorded frames are written to. Sinks are written to from the recorder's writer
+/// thread, not the one rendering.
+pub trait FrameSink: Send {
+    fn write(&mut self, frame: &Frame) -> Result<()>;
+    /// Called once after the last frame. `audio` is the track to go with the video, and the
+    /// offset into it that the first frame lines up with.
+    fn finish(self: Box<Self>, audio: Option<(&str, f64)>) -> Result<()>;
+}
+
+/// Pipes frames into ffmpeg, then muxes in the audio once it's done.
+pub struct VideoSink {
+    ffmpeg: Child,
+    pixel_input: Option<ChildStdin>,
+    // we cant render with unorm, but we also cant save with sfloat, so a conversion is required
+    unorm_buffer: Vec<u8>,
+    video_file: String,
+    encoder: EncoderSettings,
+    transform: OutputTransform,
+}
+impl VideoSink {
+    pub fn new(
+        encoder: EncoderSettings,
+        transform: OutputTransform,
+        width: u32,
+        height: u32,
+        frame_rate: FrameRate,
+    ) -> Result<Self> {
+        // the video is encoded without audio first, and only muxed into the output once it's done
+        let output_path = Path::new(&encoder.output);
+        let video_file = output_path
+            .with_extension(match output_path.extension() {
+                Some(extension) => format!("video.{}", extension.to_string_lossy()),
+                None => "video".into(),
+            })
+            .to_string_lossy()
+            .into_owned();
+
+        let (ffmpeg, pixel_input) =
+            encoder.spawn_video_stream(width as usize, height as usize, frame_rate, &video_file)?;
+
+        Ok(Self {
+            ffmpeg,
+            pixel_input: Some(pixel_input),
+            unorm_buffer: vec![0; width as usize * height as usize * 4],
+            video_file,
+            encoder,
+            transform,
+        })
+    }
+}
+impl FrameSink for VideoSink {
+    fn write(&mut self, frame: &Frame) -> Result<()> {
+        self.transform.apply(frame, &mut self.unorm_buffer);
+        self.pixel_input
+            .as_mut()
+            .unwrap()
+            .write_all(&self.unorm_buffer)?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>, audio: Option<(&str, f64)>) -> Result<()> {
+        drop(self.pixel_input.take());
+        let status = self.ffmpeg.wait()?;
+        if !status.success() {
+            bail!("ffmpeg couldn't encode {} ({status})", self.video_file);
+        }
+        match audio {
+            Some((audio_file, audio_offset)) => {
+                let status = self
+                    .encoder
+                    .merge_av(audio_file, audio_offset, &self.video_file)?
+                    .wait()?;
+                // the video without audio is all there is until the mux works, so keep it
+                if !status.success() {
+                    bail!(
+                        "ffmpeg couldn't add {audio_file} to the video ({status}); it's still \
+                         in {} without audio",
+                        self.video_file
+                    );
+                }
+                fs::remove_file(&self.video_file)?;
+            }
+            None => fs::rename(&self.video_file, &self.encoder.output)?,
+        }
+        Ok(())
+    }
+}
+
+/// Which kind of sink to record into, as picked on the command line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SinkKind {
+    Video,
+    Sequence(SequenceFormat),
+    Y4m(Chroma),
+    Avi,
+}
+impl SinkKind {
+    pub fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "video" | "ffmpeg" => SinkKind::Video,
+            "png" => SinkKind::Sequence(SequenceFormat::Png),
+            "png16" => SinkKind::Sequence(SequenceFormat::Png16),
+            "exr" => SinkKind::Sequence(SequenceFormat::Exr),
+            "y4m" => SinkKind::Y4m(Chroma::C420),
+            "y4m444" => SinkKind::Y4m(Chroma::C444),
+            "avi" => SinkKind::Avi,
+            _ => {
+                bail!("unknown sink `{name}`; expected video, png, png16, exr, y4m, y4m444 or avi")
+            }
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SequenceFormat {
+    Png,
+    /// 16 bits per channel png. Values still go through the [`OutputTransform`].
+    Png16,
+    /// Full precision float OpenEXR, with nothing clamped.
+    Exr,
+}
+impl SequenceFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SequenceFormat::Png | SequenceFormat::Png16 => "png",
+            SequenceFormat::Exr => "exr",
+        }
+    }
+}
+
+/// Writes every frame to its own numbered image file.
+pub struct ImageSequenceSink {
+    format: SequenceFormat,
+    pattern: String,
+    /// Used for png. Exr frames are always written as they were rendered.
+    transform: OutputTransform,
+}
+impl ImageSequenceSink {
+    /// `pattern` is the path of each frame, with a run of `#`s that's replaced with the
+    /// zero-padded frame index, like `frames/frame_######.png`.
+    pub fn new(
+        format: SequenceFormat,
+        pattern: impl Into<String>,
+        transform: OutputTransform,
+    ) -> Result<Self> {
+        let pattern = pattern.into();
+        if !pattern.contains('#') {
+            bail!("frame pattern `{pattern}` has no `#`s to put the frame number in");
+        }
+        if let Some(parent) = Path::new(&pattern).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(Self {
+            format,
+            pattern,
+            transform,
+        })
+    }
+
+    pub fn path(&self, index: usize) -> String {
+        let start = self.pattern.find('#').unwrap();
+        let width = self.pattern[start..]
+            .find(|c| c != '#')
+            .unwrap_or(self.pattern.len() - start);
+        format!(
+            "{}{index:0width$}{}",
+            &self.pattern[..start],
+            &self.pattern[start + width..]
+        )
+    }
+}
+impl FrameSink for ImageSequenceSink {
+    fn write(&mut self, frame: &Frame) -> Result<()> {
+        let path = self.path(frame.index);
+        let invalid_size = || anyhow!("frame data doesn't match its size");
+        match self.format {
+            SequenceFormat::Png => {
+                let mut image = RgbaImage::new(frame.width, frame.height);
+                self.transform.apply(frame, &mut image);
+                image.save_with_format(path, ImageFormat::Png)?;
+            }
+            SequenceFormat::Png16 => {
+                let mut image = ImageBuffer::<Rgba<u16>, _>::new(frame.width, frame.height);
+                self.transform.apply_16(frame, &mut image);
+                image.save_with_format(path, ImageFormat::Png)?;
+            }
+            SequenceFormat::Exr => {
+                let image =
+                    Rgba32FImage::from_raw(frame.width, frame.height, frame.pixels.to_vec())
+                        .ok_or_else(invalid_size)?;
+                image.save_with_format(path, ImageFormat::OpenExr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>, audio: Option<(&str, f64)>) -> Result<()> {
+        if let Some((audio_file, audio_offset)) = audio {
+            println!(
+                "frames in {} line up with {audio_file} from {audio_offset}s",
+                self.pattern
+            );
+        }
+        Ok(())
+    }
+}
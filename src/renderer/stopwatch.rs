@@ -1,4 +1,4 @@
-use std::{ops::Deref, time::{Duration, Instant}};
+use std::time::{Duration, Instant};
 
 pub struct Stopwatch(Instant);
 impl Stopwatch {
@@ -1,3 +1,148 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::{
+    command_buffer::{
+        allocator::CommandBufferAllocator, AutoCommandBufferBuilder, RenderPassBeginInfo,
+        SubpassBeginInfo, SubpassContents,
+    },
+    device::Queue,
+    format::Format,
+    image::{view::ImageView, Image, ImageCreateInfo, ImageType, ImageUsage, SampleCount},
+    memory::allocator::{AllocationCreateInfo, MemoryAllocator},
+    pipeline::graphics::viewport::{Scissor, Viewport},
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass},
+    sync::GpuFuture,
+};
+
+use super::app::CommandBuilder;
+
+/// Somewhere rendered frames go, like a window or a recording.
+///
+/// Frames are drawn into an offscreen image, and every target takes them from there. Each frame,
+/// targets get ready for it before it's drawn, record whatever commands take the finished image to
+/// them, and follow up once those commands have been submitted.
 pub trait RenderTarget {
+    /// Gets ready for the next frame. Nothing is drawn until the returned future is done.
+    fn prepare(&mut self) -> Result<Option<Box<dyn GpuFuture>>> {
+        Ok(None)
+    }
+
+    /// Records taking `frame`, the image that's just been drawn, to this target.
+    fn record(&mut self, _builder: &mut CommandBuilder, _frame: Arc<Image>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once the frame's commands are submitted, with `rendered` done when they are.
+    fn submitted(&mut self, _queue: &Arc<Queue>, _rendered: Box<dyn GpuFuture>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called after the last frame, with the track to go with it and the offset into the track
+    /// that the first frame lines up with.
+    fn finish(&mut self, _audio: Option<(&str, f64)>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Framebuffers for drawing into each of `images` with `render_pass`, sharing one depth buffer and,
+/// if the render pass is multisampled, one multisampled image that resolves into them.
+pub fn create_framebuffers(
+    images: &[Arc<Image>],
+    render_pass: &Arc<RenderPass>,
+    allocator: Arc<dyn MemoryAllocator>,
+) -> Result<Vec<Arc<Framebuffer>>> {
+    let samples = render_pass.attachments()[0].samples;
+    let depth_buffer = ImageView::new_default(Image::new(
+        allocator.clone(),
+        ImageCreateInfo {
+            image_type: ImageType::Dim2d,
+            format: Format::D16_UNORM,
+            extent: images[0].extent(),
+            usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
+            samples,
+            ..Default::default()
+        },
+        AllocationCreateInfo::default(),
+    )?)?;
+    // only multisampled render passes draw into something other than the images themselves
+    let multisampled_buffer = if samples != SampleCount::Sample1 {
+        Some(ImageView::new_default(Image::new(
+            allocator,
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: images[0].format(),
+                extent: images[0].extent(),
+                usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
+                samples,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )?)?)
+    } else {
+        None
+    };
+
+    images
+        .iter()
+        .map(|image| {
+            let view = ImageView::new_default(image.clone())?;
+            let attachments = match &multisampled_buffer {
+                Some(multisampled_buffer) => {
+                    vec![multisampled_buffer.clone(), depth_buffer.clone(), view]
+                }
+                None => vec![view, depth_buffer.clone()],
+            };
+            Ok(Framebuffer::new(
+                render_pass.clone(),
+                FramebufferCreateInfo {
+                    attachments,
+                    ..Default::default()
+                },
+            )?)
+        })
+        .collect::<Result<Vec<Arc<Framebuffer>>>>()
+}
 
+/// Begins a render pass on `framebuffer`, with the viewport and scissor covering all of it.
+pub fn begin_render_pass<L, A: CommandBufferAllocator + 'static>(
+    builder: &mut AutoCommandBufferBuilder<L, A>,
+    framebuffer: Arc<Framebuffer>,
+    clear_color: [f32; 4],
+) -> Result<()> {
+    let [width, height] = framebuffer.extent();
+    // the resolve attachment, if there is one, is never cleared
+    let mut clear_values = vec![Some(clear_color.into()), Some(1.0f32.into())];
+    clear_values.resize(framebuffer.attachments().len(), None);
+    builder
+        .begin_render_pass(
+            RenderPassBeginInfo {
+                clear_values,
+                ..RenderPassBeginInfo::framebuffer(framebuffer)
+            },
+            SubpassBeginInfo {
+                contents: SubpassContents::Inline,
+                ..Default::default()
+            },
+        )?
+        .set_viewport(
+            0,
+            [Viewport {
+                offset: [0.0, 0.0],
+                extent: [width as f32, height as f32],
+                depth_range: 0.0..=1.0,
+            }]
+            .into_iter()
+            .collect(),
+        )?
+        .set_scissor(
+            0,
+            [Scissor {
+                offset: [0, 0],
+                extent: [width, height],
+            }]
+            .into_iter()
+            .collect(),
+        )?;
+    Ok(())
 }
@@ -0,0 +1,529 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+/// A constant tempo, and where the first beat falls in the track.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Tempo {
+    pub bpm: f64,
+    /// Seconds into the track that beat 0 lands on.
+    pub offset: f64,
+}
+impl Tempo {
+    pub const fn new(bpm: f64, offset: f64) -> Self {
+        Self { bpm, offset }
+    }
+
+    pub fn beat(&self, seconds: f64) -> f64 {
+        (seconds - self.offset) * self.bpm / 60.0
+    }
+    pub fn seconds(&self, beat: f64) -> f64 {
+        beat * 60.0 / self.bpm + self.offset
+    }
+}
+
+impl From<Tempo> for TempoMap {
+    fn from(tempo: Tempo) -> Self {
+        TempoMap::new(tempo.bpm, tempo.offset)
+    }
+}
+
+/// A time signature. Beats are always quarter notes, so a bar of 6/8 is 3 beats long.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Meter {
+    pub beats_per_bar: u32,
+    pub beat_unit: u32,
+}
+impl Meter {
+    pub const COMMON: Meter = Meter {
+        beats_per_bar: 4,
+        beat_unit: 4,
+    };
+
+    /// How many beats a bar of this meter lasts.
+    pub fn bar_length(&self) -> f64 {
+        self.beats_per_bar as f64 * 4.0 / self.beat_unit as f64
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct TempoChange {
+    beat: f64,
+    bpm: f64,
+    /// Whether the tempo slides linearly (per beat) into the next change's, rather than jumping.
+    ramp: bool,
+    /// Seconds into the track that `beat` lands on.
+    seconds: f64,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct MeterChange {
+    bar: i64,
+    meter: Meter,
+    /// The beat `bar` starts on.
+    beat: f64,
+}
+
+/// Where every beat and bar of a track falls, for tracks whose tempo or meter changes partway
+/// through.
+///
+/// Times before the first change carry on at the first tempo and meter. A ramp with no change
+/// after it just holds its tempo.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TempoMap {
+    /// Seconds into the track that beat 0 lands on.
+    offset: f64,
+    tempos: Vec<TempoChange>,
+    meters: Vec<MeterChange>,
+}
+impl TempoMap {
+    /// A constant tempo in 4/4, with beat 0 `offset` seconds into the track.
+    pub fn new(bpm: f64, offset: f64) -> Self {
+        Self {
+            offset,
+            tempos: vec![TempoChange {
+                beat: 0.0,
+                bpm,
+                ramp: false,
+                seconds: offset,
+            }],
+            meters: vec![MeterChange {
+                bar: 0,
+                meter: Meter::COMMON,
+                beat: 0.0,
+            }],
+        }
+    }
+
+    /// Changes to `bpm` from `beat` on, replacing any change already there. With `ramp`, the tempo
+    /// then slides into the next change's instead of holding.
+    pub fn set_tempo(&mut self, beat: f64, bpm: f64, ramp: bool) -> Result<&mut Self> {
+        if !(bpm.is_finite() && bpm > 0.0) {
+            bail!("invalid tempo {bpm}");
+        }
+        if !beat.is_finite() {
+            bail!("invalid beat {beat}");
+        }
+        let change = TempoChange {
+            beat,
+            bpm,
+            ramp,
+            seconds: 0.0,
+        };
+        match self
+            .tempos
+            .binary_search_by(|other| other.beat.total_cmp(&beat))
+        {
+            Ok(index) => self.tempos[index] = change,
+            Err(index) => self.tempos.insert(index, change),
+        }
+
+        // everything after the change moves
+        self.tempos[0].seconds = self.offset;
+        for index in 1..self.tempos.len() {
+            let previous = self.tempos[index - 1];
+            self.tempos[index].seconds = previous.seconds
+                + self.segment_seconds(index - 1, self.tempos[index].beat - previous.beat);
+        }
+        Ok(self)
+    }
+
+    /// Changes to `meter` from the start of `bar` on, replacing any change already there.
+    pub fn set_meter(&mut self, bar: i64, meter: Meter) -> Result<&mut Self> {
+        if meter.beats_per_bar == 0 || meter.beat_unit == 0 {
+            bail!("invalid meter {}/{}", meter.beats_per_bar, meter.beat_unit);
+        }
+        let change = MeterChange {
+            bar,
+            meter,
+            beat: 0.0,
+        };
+        match self.meters.binary_search_by_key(&bar, |other| other.bar) {
+            Ok(index) => self.meters[index] = change,
+            Err(index) => self.meters.insert(index, change),
+        }
+
+        // bar 0 always starts on beat 0, whatever meter it's in
+        let first = self.meters[0];
+        self.meters[0].beat = first.bar as f64 * first.meter.bar_length();
+        for index in 1..self.meters.len() {
+            let previous = self.meters[index - 1];
+            self.meters[index].beat = previous.beat
+                + (self.meters[index].bar - previous.bar) as f64 * previous.meter.bar_length();
+        }
+        Ok(self)
+    }
+
+    pub fn offset(&self) -> f64 {
+        self.offset
+    }
+
+    /// The tempo right at `beat`.
+    pub fn bpm(&self, beat: f64) -> f64 {
+        let index = self.tempo_at(|change| change.beat <= beat);
+        let change = self.tempos[index];
+        match self.ramp_to(index) {
+            Some(next) if beat > change.beat => {
+                change.bpm
+                    + (next.bpm - change.bpm) * (beat - change.beat) / (next.beat - change.beat)
+            }
+            _ => change.bpm,
+        }
+    }
+
+    pub fn meter(&self, beat: f64) -> Meter {
+        self.meters[self.meter_at(|change| change.beat <= beat)].meter
+    }
+
+    pub fn beat(&self, seconds: f64) -> f64 {
+        let index = self.tempo_at(|change| change.seconds <= seconds);
+        let change = self.tempos[index];
+        let elapsed = seconds - change.seconds;
+        let beats = match self.ramp_to(index) {
+            // the tempo grows linearly with beats, so it grows exponentially with time
+            Some(next) if elapsed > 0.0 => {
+                let length = next.beat - change.beat;
+                let slope = (next.bpm - change.bpm) / length;
+                let bpm = change.bpm * (elapsed * slope / 60.0).exp();
+                (bpm - change.bpm) / slope
+            }
+            _ => elapsed * change.bpm / 60.0,
+        };
+        change.beat + beats
+    }
+
+    pub fn seconds(&self, beat: f64) -> f64 {
+        let index = self.tempo_at(|change| change.beat <= beat);
+        let change = self.tempos[index];
+        change.seconds + self.segment_seconds(index, beat - change.beat)
+    }
+
+    /// Which bar `beat` is in, with how far through it as the fraction.
+    pub fn bar(&self, beat: f64) -> f64 {
+        let change = self.meters[self.meter_at(|change| change.beat <= beat)];
+        change.bar as f64 + (beat - change.beat) / change.meter.bar_length()
+    }
+
+    /// The beat that `bar` starts on. Fractional bars land partway through.
+    pub fn beat_of_bar(&self, bar: f64) -> f64 {
+        let change = self.meters[self.meter_at(|change| change.bar as f64 <= bar)];
+        change.beat + (bar - change.bar as f64) * change.meter.bar_length()
+    }
+
+    /// The bar `beat` is in, and how many beats into that bar it is.
+    pub fn bar_and_beat(&self, beat: f64) -> (i64, f64) {
+        let bar = self.bar(beat).floor();
+        (bar as i64, beat - self.beat_of_bar(bar))
+    }
+
+    /// Parses a tempo map, one change per line:
+    ///
+    /// ```text
+    /// # everything after a # is ignored
+    /// offset 0.0134        # seconds into the track that beat 0 lands on
+    /// tempo 0 120          # 120 bpm from beat 0
+    /// tempo 64 120 ramp    # from beat 64, slide into...
+    /// tempo 96 140         # ...140 bpm at beat 96
+    /// meter 0 4/4          # 4/4 from bar 0
+    /// meter 16 7/8
+    /// ```
+    ///
+    /// There has to be a tempo at beat 0. The meter is 4/4 unless it says otherwise.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut offset = 0.0;
+        let mut tempos = Vec::new();
+        let mut meters = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let words: Vec<&str> = match line.split_once('#') {
+                Some((line, _)) => line,
+                None => line,
+            }
+            .split_whitespace()
+            .collect();
+            let parse = |word: &str| -> Result<f64> {
+                word.parse()
+                    .with_context(|| format!("line {}: `{word}` isn't a number", number + 1))
+            };
+            match words[..] {
+                [] => {}
+                ["offset", seconds] => offset = parse(seconds)?,
+                ["tempo", beat, bpm] => tempos.push((parse(beat)?, parse(bpm)?, false)),
+                ["tempo", beat, bpm, "ramp"] => tempos.push((parse(beat)?, parse(bpm)?, true)),
+                ["meter", bar, meter] => {
+                    let invalid = || anyhow!("line {}: invalid meter `{meter}`", number + 1);
+                    let (beats_per_bar, beat_unit) = meter.split_once('/').ok_or_else(invalid)?;
+                    meters.push((
+                        bar.parse()
+                            .with_context(|| format!("line {}: invalid bar `{bar}`", number + 1))?,
+                        Meter {
+                            beats_per_bar: beats_per_bar.parse().map_err(|_| invalid())?,
+                            beat_unit: beat_unit.parse().map_err(|_| invalid())?,
+                        },
+                    ));
+                }
+                _ => bail!(
+                    "line {}: expected offset, tempo or meter, got `{line}`",
+                    number + 1
+                ),
+            }
+        }
+
+        let Some(&(_, bpm, _)) = tempos.iter().find(|&&(beat, ..)| beat == 0.0) else {
+            bail!("a tempo map needs a tempo at beat 0");
+        };
+        let mut map = TempoMap::new(bpm, offset);
+        for (beat, bpm, ramp) in tempos {
+            map.set_tempo(beat, bpm, ramp)?;
+        }
+        for (bar, meter) in meters {
+            map.set_meter(bar, meter)?;
+        }
+        Ok(map)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("couldn't read tempo map `{}`", path.display()))?;
+        TempoMap::parse(&text).with_context(|| format!("in tempo map `{}`", path.display()))
+    }
+
+    /// The last tempo change that `before` holds for, or the first if there isn't one.
+    fn tempo_at(&self, before: impl Fn(&TempoChange) -> bool) -> usize {
+        self.tempos.partition_point(before).saturating_sub(1)
+    }
+    fn meter_at(&self, before: impl Fn(&MeterChange) -> bool) -> usize {
+        self.meters.partition_point(before).saturating_sub(1)
+    }
+
+    /// The change that the one at `index` ramps into, if it does.
+    fn ramp_to(&self, index: usize) -> Option<TempoChange> {
+        let next = self.tempos.get(index + 1).copied()?;
+        (self.tempos[index].ramp && next.bpm != self.tempos[index].bpm).then_some(next)
+    }
+
+    /// How long the `beats` after the change at `index` take.
+    fn segment_seconds(&self, index: usize, beats: f64) -> f64 {
+        let change = self.tempos[index];
+        match self.ramp_to(index) {
+            Some(next) if beats > 0.0 => {
+                let slope = (next.bpm - change.bpm) / (next.beat - change.beat);
+                let bpm = change.bpm + slope * beats;
+                60.0 / slope * (bpm / change.bpm).ln()
+            }
+            _ => beats * 60.0 / change.bpm,
+        }
+    }
+}
+
+/// How finely bars:beats:ticks times split a beat.
+pub const TICKS_PER_BEAT: u32 = 480;
+
+/// A point in a piece, as given on the command line or for a keyframe.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TimePoint {
+    Seconds(f64),
+    Beats(f64),
+    /// A bar, and how many beats into it. Bars count from 0, as [`TempoMap::bar`] does.
+    Bar(i64, f64),
+}
+impl TimePoint {
+    /// Parses `12.5`/`12.5s` as seconds, `32b` as beats, and `8:2:240` as bars:beats:ticks,
+    /// where the ticks can be left off and there are [`TICKS_PER_BEAT`] of them to a beat.
+    pub fn parse(text: &str) -> Result<Self> {
+        if text.contains(':') {
+            return Self::parse_bar(text);
+        }
+        let (number, point): (_, fn(f64) -> Self) = match text.strip_suffix('b') {
+            Some(beats) => (beats, TimePoint::Beats),
+            None => (text.strip_suffix('s').unwrap_or(text), TimePoint::Seconds),
+        };
+        let number: f64 = number
+            .parse()
+            .with_context(|| format!("expected a time like 12.5s or 32b, got `{text}`"))?;
+        if !number.is_finite() {
+            bail!("time `{text}` isn't finite");
+        }
+        Ok(point(number))
+    }
+
+    fn parse_bar(text: &str) -> Result<Self> {
+        let invalid =
+            || anyhow!("expected a time like 8:2 or 8:2:240 (bars:beats:ticks), got `{text}`");
+        let mut parts = text.split(':');
+        let bar: i64 = parts.next().unwrap().parse().map_err(|_| invalid())?;
+        let beat: f64 = parts.next().unwrap().parse().map_err(|_| invalid())?;
+        let ticks: f64 = match parts.next() {
+            Some(ticks) => ticks.parse().map_err(|_| invalid())?,
+            None => 0.0,
+        };
+        if parts.next().is_some() || !(beat.is_finite() && ticks.is_finite()) {
+            return Err(invalid());
+        }
+        if beat < 0.0 || ticks < 0.0 {
+            bail!("time `{text}` can't have a negative beat or tick");
+        }
+        Ok(TimePoint::Bar(bar, beat + ticks / TICKS_PER_BEAT as f64))
+    }
+
+    /// Resolves this to beats into the piece.
+    pub fn beat(&self, tempo: &TempoMap) -> f64 {
+        match *self {
+            TimePoint::Seconds(seconds) => tempo.beat(seconds),
+            TimePoint::Beats(beat) => beat,
+            TimePoint::Bar(bar, beat) => tempo.beat_of_bar(bar as f64) + beat,
+        }
+    }
+
+    /// Resolves this to seconds into the piece. Beats and bars need the piece to have a tempo.
+    pub fn seconds(&self, tempo: Option<&TempoMap>) -> Result<f64> {
+        match *self {
+            TimePoint::Seconds(seconds) => Ok(seconds),
+            _ => {
+                let tempo = tempo.ok_or(anyhow!(
+                    "this piece has no tempo, so times can't be given in beats or bars"
+                ))?;
+                Ok(tempo.seconds(self.beat(tempo)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    fn meter(beats_per_bar: u32, beat_unit: u32) -> Meter {
+        Meter {
+            beats_per_bar,
+            beat_unit,
+        }
+    }
+
+    /// 120 bpm, sliding from beat 8 into 180 bpm at beat 16.
+    fn ramp() -> TempoMap {
+        let mut map = TempoMap::new(120.0, 0.25);
+        map.set_tempo(8.0, 120.0, true)
+            .unwrap()
+            .set_tempo(16.0, 180.0, false)
+            .unwrap();
+        map
+    }
+
+    /// 120 bpm in 4/4, then 90 bpm in 7/8 from bar 2 (beat 8), then 3/4 from bar 4 (beat 15).
+    fn meters() -> TempoMap {
+        let mut map = TempoMap::new(120.0, 0.0);
+        map.set_tempo(8.0, 90.0, false).unwrap();
+        map.set_meter(2, meter(7, 8))
+            .unwrap()
+            .set_meter(4, meter(3, 4))
+            .unwrap();
+        map
+    }
+
+    #[test]
+    fn ramp_seconds() {
+        let map = ramp();
+        assert!(close(map.seconds(8.0), 4.25));
+        // halfway through, at 150 bpm
+        assert!(close(map.bpm(12.0), 150.0));
+        assert!(close(map.seconds(12.0), 4.25 + 8.0 * 1.25f64.ln()));
+        assert!(close(map.seconds(16.0), 4.25 + 8.0 * 1.5f64.ln()));
+        // and it holds 180 after
+        assert!(close(map.seconds(20.0), map.seconds(16.0) + 4.0 / 3.0));
+    }
+
+    #[test]
+    fn ramp_round_trip() {
+        let map = ramp();
+        for i in -8..=48 {
+            let beat = i as f64 / 2.0;
+            assert!(close(map.beat(map.seconds(beat)), beat), "beat {beat}");
+        }
+        for i in 0..=120 {
+            let seconds = i as f64 / 10.0;
+            assert!(close(map.seconds(map.beat(seconds)), seconds), "{seconds}s");
+        }
+    }
+
+    #[test]
+    fn meter_change() {
+        let map = meters();
+        assert!(close(map.beat_of_bar(2.0), 8.0));
+        assert!(close(map.beat_of_bar(4.0), 15.0));
+        assert!(close(map.beat_of_bar(5.0), 18.0));
+        assert_eq!(map.meter(14.9), meter(7, 8));
+
+        // bar 3, beat 1 is beat 12.5, which is 4s at 120 bpm and 3s at 90
+        let point = TimePoint::Bar(3, 1.0);
+        assert!(close(point.beat(&map), 12.5));
+        assert!(close(point.seconds(Some(&map)).unwrap(), 7.0));
+        assert!(close(map.beat(7.0), 12.5));
+        assert!(close(map.bar(12.5), 2.0 + 4.5 / 3.5));
+        for i in 0..=60 {
+            let beat = i as f64 / 3.0;
+            assert!(close(map.beat(map.seconds(beat)), beat), "beat {beat}");
+        }
+    }
+
+    #[test]
+    fn bar_and_beat_at_boundaries() {
+        let map = meters();
+        assert_eq!(map.bar_and_beat(0.0), (0, 0.0));
+        assert_eq!(map.bar_and_beat(8.0), (2, 0.0));
+        assert_eq!(map.bar_and_beat(15.0), (4, 0.0));
+        assert_eq!(map.bar_and_beat(14.5), (3, 3.0));
+        let (bar, beat) = map.bar_and_beat(8.0 - 1e-6);
+        assert_eq!(bar, 1);
+        assert!((beat - 4.0).abs() < 1e-5);
+        // before the first bar, it carries on counting back in 4/4
+        assert_eq!(map.bar_and_beat(-1.0), (-1, 3.0));
+    }
+
+    #[test]
+    fn parse() {
+        let map = TempoMap::parse(
+            "# a comment\n\
+             offset 0.25\n\
+             tempo 0 120\n\
+             tempo 8 120 ramp  # slide...\n\
+             \n\
+             tempo 16 180\n",
+        )
+        .unwrap();
+        assert_eq!(map, ramp());
+        assert_eq!(
+            TempoMap::parse("tempo 0 120\nmeter 2 7/8\nmeter 4 3/4\ntempo 8 90").unwrap(),
+            meters()
+        );
+    }
+
+    #[test]
+    fn parse_rejects_malformed_lines() {
+        for text in [
+            "",
+            "offset",
+            "offset soon",
+            "tempo 0",
+            "tempo 0 fast",
+            "tempo 0 120 slide",
+            "tempo 0 -120",
+            "tempo 0 0",
+            "tempo 4 120",
+            "bpm 120",
+            "tempo 0 120\nmeter 0 7-8",
+            "tempo 0 120\nmeter 0 0/4",
+            "tempo 0 120\nmeter 0 4/x",
+            "tempo 0 120\nmeter 1.5 4/4",
+            "tempo 0 120\nmeter 0 4/4 4/4",
+        ] {
+            assert!(TempoMap::parse(text).is_err(), "{text:?} parsed");
+        }
+        let error = TempoMap::parse("tempo 0 120\ntempo 8 fast").unwrap_err();
+        assert!(error.to_string().starts_with("line 2"), "{error}");
+    }
+}
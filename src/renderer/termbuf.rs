@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
 #[derive(Zeroable, Pod, Clone, Copy)]
 #[repr(C)]
@@ -63,6 +63,19 @@ pub const PANEL_VERTICES: [vertex::CommonVertex; 4] = [
 ];
 pub const PANEL_INDICES: [u32; 6] = [0, 1, 2, 3, 2, 1];
 
+static CHARSET: OnceLock<String> = OnceLock::new();
+
+/// The image characters are drawn from; `charset.png` unless a project picked another one.
+pub fn charset() -> &'static str {
+    CHARSET.get().map_or("charset.png", String::as_str)
+}
+
+/// Picks the image [`charset`] returns. Only the first call counts, since it's meant to be set
+/// once at startup.
+pub fn set_charset(path: String) {
+    let _ = CHARSET.set(path);
+}
+
 #[derive(Clone)]
 pub struct TerminalPanel {
     width: u32,
@@ -87,6 +100,7 @@ pub struct TerminalPanel {
     pub background_buffer: Subbuffer<[Color]>,
 }
 impl TerminalPanel {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         width: u32,
         height: u32,
@@ -109,6 +123,7 @@ impl TerminalPanel {
             indices,
         )
     }
+    #[allow(clippy::too_many_arguments)]
     pub fn with_transform(
         width: u32,
         height: u32,
@@ -124,7 +139,7 @@ impl TerminalPanel {
             Some(charset) => charset,
             None => {
                 loader_command_buffer
-                    .load_image("charset.png", allocator.clone(), ImageUsage::SAMPLED)?
+                    .load_image(self::charset(), allocator.clone(), ImageUsage::SAMPLED)?
                     .1
             }
         };
@@ -347,13 +362,16 @@ impl TerminalPanel {
     }
 
     pub fn fill_chars(&self, character: u8) -> Result<()> {
-        Ok(self.character_buffer.write()?.fill(character))
+        self.character_buffer.write()?.fill(character);
+        Ok(())
     }
     pub fn fill_fg(&self, color: Color) -> Result<()> {
-        Ok(self.foreground_buffer.write()?.fill(color))
+        self.foreground_buffer.write()?.fill(color);
+        Ok(())
     }
     pub fn fill_bg(&self, color: Color) -> Result<()> {
-        Ok(self.background_buffer.write()?.fill(color))
+        self.background_buffer.write()?.fill(color);
+        Ok(())
     }
     pub fn width(&self) -> u32 {
         self.width
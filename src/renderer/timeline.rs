@@ -0,0 +1,536 @@
+use std::{ops::Range, slice, sync::Arc};
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use vulkano::{
+    buffer::BufferContents,
+    command_buffer::SubpassEndInfo,
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, PersistentDescriptorSet, WriteDescriptorSet,
+    },
+    device::Device,
+    image::{
+        sampler::{Sampler, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage, SampleCount,
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryAllocator},
+    pipeline::{
+        graphics::{
+            color_blend::ColorBlendState,
+            depth_stencil::DepthStencilState,
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::VertexInputState,
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    render_pass::{Framebuffer, RenderPass, Subpass},
+};
+
+use super::{
+    app::{AppEntry, CommandBuilder, DynApp, FrameContext},
+    target::{begin_render_pass, create_framebuffers},
+};
+
+/// How a scene takes over from the one before it.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transition {
+    Cut,
+    /// Fades from the last scene into this one over this many beats.
+    Crossfade(f64),
+    /// Sweeps this scene in from the left over this many beats.
+    Wipe(f64),
+}
+impl Transition {
+    fn beats(self) -> f64 {
+        match self {
+            Transition::Cut => 0.0,
+            Transition::Crossfade(beats) | Transition::Wipe(beats) => beats,
+        }
+    }
+}
+
+/// Where a scene sits on the timeline, and how it comes in.
+#[derive(Clone, Debug)]
+struct Cue {
+    beats: Range<f64>,
+    transition: Transition,
+}
+
+/// Runs `f` with the context's time, beat and bar counted from `start` (in beats) rather than from
+/// the start of the piece.
+fn local_time<T>(
+    context: &mut FrameContext,
+    start: f64,
+    f: impl FnOnce(&mut FrameContext) -> T,
+) -> T {
+    let global = (context.time, context.beat, context.bar);
+    context.time -= context.tempo().seconds(start);
+    context.beat -= start;
+    context.bar -= context.tempo().bar(start);
+    let result = f(context);
+    (context.time, context.beat, context.bar) = global;
+    result
+}
+
+/// What's on screen at some beat.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Showing {
+    Nothing,
+    Scene(usize),
+    /// Part way into a scene's transition, from the scene before it if it's still running.
+    Transition {
+        from: Option<usize>,
+        to: usize,
+        transition: Transition,
+        progress: f32,
+    },
+}
+
+/// What's on screen at `beat`, given `cues` in the order they start.
+fn showing(cues: &[Cue], beat: f64) -> Showing {
+    let Some(index) = cues.iter().rposition(|cue| cue.beats.start <= beat) else {
+        return Showing::Nothing;
+    };
+    let cue = &cues[index];
+    let length = cue.transition.beats();
+    let progress = (beat - cue.beats.start) / length;
+    if length > 0.0 && progress < 1.0 {
+        // scenes that ended before this one started have nothing left to transition from
+        let from = index
+            .checked_sub(1)
+            .filter(|&from| cues[from].beats.end >= cue.beats.start);
+        return Showing::Transition {
+            from,
+            to: index,
+            transition: cue.transition,
+            progress: progress as f32,
+        };
+    }
+    if cue.beats.contains(&beat) {
+        Showing::Scene(index)
+    } else {
+        Showing::Nothing
+    }
+}
+
+/// Sets the transition shader up, matching its push constants.
+#[derive(BufferContents)]
+#[repr(C)]
+struct Blend {
+    progress: f32,
+    kind: u32,
+}
+
+/// Scenes on ranges of beats, played one after the other as a single app. Each scene is an
+/// ordinary [`App`](super::app::App), and sees time, beats and bars counted from the start of its
+/// own range.
+///
+/// While a scene crossfades or wipes in, it and the scene before it are drawn into images of their
+/// own, which then get blended into the frame.
+///
+/// Timelines aren't apps themselves, so they're registered by filling in an [`AppEntry`] by hand,
+/// with a constructor that builds the timeline, or set up in a
+/// [`Project`](crate::project::Project).
+pub struct Timeline {
+    cues: Vec<Cue>,
+    /// The scene for each cue.
+    scenes: Vec<Box<dyn DynApp>>,
+    allocator: Arc<dyn MemoryAllocator>,
+    device: Arc<Device>,
+    render_pass: Arc<RenderPass>,
+    viewport: Viewport,
+    /// What the outgoing and incoming scenes of a transition get drawn into.
+    layers: [Arc<Framebuffer>; 2],
+    layers_set: Arc<PersistentDescriptorSet>,
+    pipeline: Arc<GraphicsPipeline>,
+    showing: Showing,
+    beat: f64,
+}
+impl Timeline {
+    pub fn new(
+        allocator: Arc<dyn MemoryAllocator>,
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        viewport: Viewport,
+    ) -> Result<Self> {
+        let [width, height] = viewport.extent.map(|length| length as u32);
+        let layer = || -> Result<(Arc<Framebuffer>, Arc<ImageView>)> {
+            let image = Image::new(
+                allocator.clone(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim2d,
+                    format: render_pass.attachments()[0].format,
+                    extent: [width, height, 1],
+                    usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+                    ..Default::default()
+                },
+                AllocationCreateInfo::default(),
+            )?;
+            let framebuffers =
+                create_framebuffers(slice::from_ref(&image), &render_pass, allocator.clone())?;
+            Ok((framebuffers[0].clone(), ImageView::new_default(image)?))
+        };
+        let (outgoing, outgoing_view) = layer()?;
+        let (incoming, incoming_view) = layer()?;
+
+        let pipeline = create_pipeline(device.clone(), render_pass.clone())?;
+        let sampler = Sampler::new(device.clone(), SamplerCreateInfo::default())?;
+        let layers_set = PersistentDescriptorSet::new(
+            &StandardDescriptorSetAllocator::new(device.clone(), Default::default()),
+            pipeline.layout().set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::image_view_sampler(0, outgoing_view, sampler.clone()),
+                WriteDescriptorSet::image_view_sampler(1, incoming_view, sampler),
+            ],
+            [],
+        )?;
+
+        Ok(Self {
+            cues: Vec::new(),
+            scenes: Vec::new(),
+            allocator,
+            device,
+            render_pass,
+            viewport,
+            layers: [outgoing, incoming],
+            layers_set,
+            pipeline,
+            showing: Showing::Nothing,
+            beat: 0.0,
+        })
+    }
+
+    /// Adds the app `entry` stands for as a scene that's shown over `beats`, coming in with
+    /// `transition`. Scenes have to be added in the order they start; a scene that starts before
+    /// the last one ends cuts it short.
+    pub fn entry(
+        mut self,
+        loader_command_buffer: &mut CommandBuilder,
+        entry: &AppEntry,
+        beats: Range<f64>,
+        transition: Transition,
+    ) -> Result<Self> {
+        if beats.is_empty() {
+            bail!("scene `{}` has no beats to be shown on", entry.name);
+        }
+        if self
+            .cues
+            .last()
+            .is_some_and(|last| beats.start < last.beats.start)
+        {
+            bail!("scenes have to be added in the order they start");
+        }
+        let scene = (entry.new)(
+            loader_command_buffer,
+            self.allocator.clone(),
+            self.device.clone(),
+            self.render_pass.clone(),
+            self.viewport.clone(),
+        )?;
+        self.cues.push(Cue { beats, transition });
+        self.scenes.push(scene);
+        Ok(self)
+    }
+
+    /// Updates the scene at `index`, on its own time.
+    fn update_scene(
+        &mut self,
+        index: usize,
+        context: &mut FrameContext,
+        builder: &mut CommandBuilder,
+    ) -> Result<()> {
+        let scene = &mut self.scenes[index];
+        local_time(context, self.cues[index].beats.start, |context| {
+            scene.update(context, builder)
+        })
+    }
+
+    /// Draws the scene at `index`, on its own time.
+    fn draw_scene(
+        &mut self,
+        index: usize,
+        context: &mut FrameContext,
+        builder: &mut CommandBuilder,
+    ) -> Result<()> {
+        let scene = &mut self.scenes[index];
+        local_time(context, self.cues[index].beats.start, |context| {
+            scene.draw(context, builder)
+        })
+    }
+}
+impl DynApp for Timeline {
+    fn update(
+        &mut self,
+        context: &mut FrameContext,
+        upload_command_buffer: &mut CommandBuilder,
+    ) -> Result<()> {
+        self.beat = context.beat;
+        self.showing = showing(&self.cues, context.beat);
+        match self.showing {
+            Showing::Nothing => {}
+            Showing::Scene(index) => self.update_scene(index, context, upload_command_buffer)?,
+            Showing::Transition { from, to, .. } => {
+                // both scenes are drawn here, so the frame's render pass only has to blend them
+                for (layer, index) in self.layers.clone().into_iter().zip([from, Some(to)]) {
+                    if let Some(index) = index {
+                        self.update_scene(index, context, upload_command_buffer)?;
+                    }
+                    begin_render_pass(upload_command_buffer, layer, [0.0, 0.0, 0.0, 1.0])?;
+                    if let Some(index) = index {
+                        self.draw_scene(index, context, upload_command_buffer)?;
+                    }
+                    upload_command_buffer.end_render_pass(SubpassEndInfo::default())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn draw(
+        &mut self,
+        context: &mut FrameContext,
+        render_command_buffer: &mut CommandBuilder,
+    ) -> Result<()> {
+        match self.showing {
+            Showing::Nothing => {}
+            Showing::Scene(index) => self.draw_scene(index, context, render_command_buffer)?,
+            Showing::Transition {
+                transition,
+                progress,
+                ..
+            } => {
+                let kind = match transition {
+                    Transition::Wipe(_) => 1,
+                    _ => 0,
+                };
+                render_command_buffer
+                    .bind_pipeline_graphics(self.pipeline.clone())?
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Graphics,
+                        self.pipeline.layout().clone(),
+                        0,
+                        self.layers_set.clone(),
+                    )?
+                    .push_constants(self.pipeline.layout().clone(), 0, Blend { progress, kind })?
+                    .draw(3, 1, 0, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn done(&self) -> bool {
+        self.cues
+            .iter()
+            .map(|cue| cue.beats.end)
+            .reduce(f64::max)
+            .is_none_or(|end| self.beat >= end)
+    }
+
+    /// The first track any scene has.
+    fn audio(&self) -> Option<&'static str> {
+        self.scenes.iter().find_map(|scene| scene.audio())
+    }
+}
+
+/// Draws a single triangle over the whole frame, blending the two layers together.
+fn create_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPass>,
+) -> Result<Arc<GraphicsPipeline>> {
+    let stages = [
+        PipelineShaderStageCreateInfo::new(
+            shaders::vertex::load(device.clone())?
+                .entry_point("main")
+                .unwrap(),
+        ),
+        PipelineShaderStageCreateInfo::new(
+            shaders::fragment::load(device.clone())?
+                .entry_point("main")
+                .unwrap(),
+        ),
+    ];
+    let layout = PipelineLayout::new(
+        device.clone(),
+        PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+            .into_pipeline_layout_create_info(device.clone())?,
+    )?;
+    let subpass = Subpass::from(render_pass, 0).unwrap();
+    Ok(GraphicsPipeline::new(
+        device,
+        None,
+        GraphicsPipelineCreateInfo {
+            stages: stages.into_iter().collect(),
+            vertex_input_state: Some(VertexInputState::default()),
+            input_assembly_state: Some(InputAssemblyState::default()),
+            viewport_state: Some(ViewportState::default()),
+            rasterization_state: Some(RasterizationState::default()),
+            multisample_state: Some(MultisampleState {
+                rasterization_samples: subpass.num_samples().unwrap_or(SampleCount::Sample1),
+                ..Default::default()
+            }),
+            color_blend_state: Some(ColorBlendState::with_attachment_states(
+                subpass.num_color_attachments(),
+                Default::default(),
+            )),
+            // no depth test; the layers already have everything in front of everything else
+            depth_stencil_state: Some(DepthStencilState::default()),
+            dynamic_state: [DynamicState::Viewport, DynamicState::Scissor]
+                .into_iter()
+                .collect(),
+            subpass: Some(subpass.into()),
+            ..GraphicsPipelineCreateInfo::layout(layout)
+        },
+    )?)
+}
+
+mod shaders {
+    pub mod vertex {
+        vulkano_shaders::shader! {
+            ty: "vertex",
+            src: r"
+                #version 460
+
+                void main() {
+                    vec2 position = vec2(gl_VertexIndex & 1, gl_VertexIndex >> 1) * 4.0 - 1.0;
+                    gl_Position = vec4(position, 0.0, 1.0);
+                }
+            ",
+        }
+    }
+
+    pub mod fragment {
+        vulkano_shaders::shader! {
+            ty: "fragment",
+            src: r"
+                #version 460
+
+                layout(set = 0, binding = 0) uniform sampler2D outgoing;
+                layout(set = 0, binding = 1) uniform sampler2D incoming;
+
+                layout(push_constant) uniform Blend {
+                    float progress;
+                    uint kind;
+                };
+
+                layout(location = 0) out vec4 color;
+
+                void main() {
+                    ivec2 pixel = ivec2(gl_FragCoord.xy);
+                    vec4 from = texelFetch(outgoing, pixel, 0);
+                    vec4 to = texelFetch(incoming, pixel, 0);
+                    if (kind == 1) {
+                        float x = gl_FragCoord.x / float(textureSize(incoming, 0).x);
+                        color = x < progress ? to : from;
+                    } else {
+                        color = mix(from, to, progress);
+                    }
+                }
+            ",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cue(beats: Range<f64>, transition: Transition) -> Cue {
+        Cue { beats, transition }
+    }
+
+    #[test]
+    fn cut() {
+        let cues = [
+            cue(0.0..8.0, Transition::Cut),
+            cue(8.0..16.0, Transition::Cut),
+        ];
+        assert_eq!(showing(&cues, -1.0), Showing::Nothing);
+        assert_eq!(showing(&cues, 0.0), Showing::Scene(0));
+        assert_eq!(showing(&cues, 7.5), Showing::Scene(0));
+        assert_eq!(showing(&cues, 8.0), Showing::Scene(1));
+        assert_eq!(showing(&cues, 16.0), Showing::Nothing);
+    }
+
+    #[test]
+    fn crossfade() {
+        let fade = Transition::Crossfade(2.0);
+        let cues = [cue(0.0..8.0, Transition::Cut), cue(8.0..16.0, fade)];
+        assert_eq!(showing(&cues, 7.5), Showing::Scene(0));
+        // the outgoing scene keeps going past its end until the fade is over
+        for (beat, progress) in [(8.0, 0.0), (9.0, 0.5), (9.5, 0.75)] {
+            assert_eq!(
+                showing(&cues, beat),
+                Showing::Transition {
+                    from: Some(0),
+                    to: 1,
+                    transition: fade,
+                    progress,
+                },
+                "beat {beat}"
+            );
+        }
+        assert_eq!(showing(&cues, 10.0), Showing::Scene(1));
+    }
+
+    #[test]
+    fn wipe_in_from_nothing() {
+        let wipe = Transition::Wipe(4.0);
+        let cues = [cue(0.0..8.0, wipe)];
+        assert_eq!(
+            showing(&cues, 1.0),
+            Showing::Transition {
+                from: None,
+                to: 0,
+                transition: wipe,
+                progress: 0.25,
+            }
+        );
+        assert_eq!(showing(&cues, 4.0), Showing::Scene(0));
+    }
+
+    #[test]
+    fn gaps() {
+        let fade = Transition::Crossfade(2.0);
+        let cues = [cue(0.0..4.0, Transition::Cut), cue(6.0..10.0, fade)];
+        assert_eq!(showing(&cues, 5.0), Showing::Nothing);
+        // the first scene is long over, so the second fades in from black
+        assert_eq!(
+            showing(&cues, 7.0),
+            Showing::Transition {
+                from: None,
+                to: 1,
+                transition: fade,
+                progress: 0.5,
+            }
+        );
+        assert_eq!(showing(&cues, 8.0), Showing::Scene(1));
+        assert_eq!(showing(&cues, 10.0), Showing::Nothing);
+    }
+
+    #[test]
+    fn overlapping_ranges() {
+        let fade = Transition::Crossfade(2.0);
+        let cues = [cue(0.0..8.0, Transition::Cut), cue(4.0..12.0, fade)];
+        assert_eq!(showing(&cues, 3.0), Showing::Scene(0));
+        assert_eq!(
+            showing(&cues, 5.0),
+            Showing::Transition {
+                from: Some(0),
+                to: 1,
+                transition: fade,
+                progress: 0.5,
+            }
+        );
+        // the later scene cuts the earlier one short
+        assert_eq!(showing(&cues, 7.0), Showing::Scene(1));
+        assert_eq!(showing(&cues, 11.0), Showing::Scene(1));
+    }
+}
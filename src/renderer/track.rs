@@ -0,0 +1,194 @@
+use glam::{Mat4, Quat, Vec3, Vec4};
+
+use super::{
+    color::{self, Color},
+    keyframe::{KeyframeSequence, Tween},
+};
+
+pub type Vec3Track = KeyframeSequence<Vec3>;
+pub type RotationTrack = KeyframeSequence<Quat>;
+/// Blends straight between the channels. For fades between very different colors,
+/// [`PerceptualColorTrack`] tends to look more even.
+pub type ColorTrack = KeyframeSequence<Color>;
+pub type PerceptualColorTrack = KeyframeSequence<PerceptualColor>;
+
+/// A color that's keyed in oklab, so blends between keys keep a steady brightness rather than
+/// going dark or muddy in the middle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PerceptualColor(pub Color);
+impl Default for PerceptualColor {
+    fn default() -> Self {
+        Self(color::TRANSPARENT)
+    }
+}
+impl From<Color> for PerceptualColor {
+    fn from(color: Color) -> Self {
+        Self(color)
+    }
+}
+impl From<PerceptualColor> for Color {
+    fn from(color: PerceptualColor) -> Self {
+        color.0
+    }
+}
+impl Tween for PerceptualColor {
+    fn tween(&self, to: &Self, mix: f32) -> Self {
+        let lab = color::to_oklab(self.0).tween(&color::to_oklab(to.0), mix);
+        Self(color::from_oklab(lab))
+    }
+    fn accumulate(&self, from: &Self, to: &Self, cycles: f32) -> Self {
+        let [lab, from, to] = [self, from, to].map(|color| color::to_oklab(color.0));
+        Self(color::from_oklab(lab.accumulate(&from, &to, cycles)))
+    }
+    fn tween_smooth(before: &Self, from: &Self, to: &Self, after: &Self, mix: f32) -> Self {
+        let [before, from, to, after] =
+            [before, from, to, after].map(|color| color::to_oklab(color.0));
+        Self(color::from_oklab(Vec4::tween_smooth(
+            &before, &from, &to, &after, mix,
+        )))
+    }
+}
+
+/// Translation, rotation and scale, each keyed on their own, and put together into one
+/// transform for [`Mesh::draw`](super::mesh::Mesh::draw). Terminal panels size themselves, so
+/// they take the translation and rotation from [`Self::parts`] through
+/// [`TerminalPanel::flat_transform`](super::termbuf::TerminalPanel::flat_transform).
+///
+/// ```ignore
+/// let spin = TransformTrack::new()
+///     .translation(Vec3Track::new().key(0.0, Vec3::ZERO, Easing::linear()))
+///     .rotation(
+///         RotationTrack::new()
+///             .key(0.0, Quat::IDENTITY, Easing::linear())
+///             .key(4.0, Quat::from_rotation_y(PI), Easing::linear())
+///             .extrapolate(Extrapolation::LoopWithOffset),
+///     );
+/// mesh.draw(allocator, render_commands, pipeline, vp * spin.sample(beat))?;
+/// ```
+#[derive(Default)]
+pub struct TransformTrack {
+    translation: Vec3Track,
+    rotation: RotationTrack,
+    /// Nothing's scaled while there are no keys.
+    scale: Vec3Track,
+}
+impl TransformTrack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn translation(mut self, track: Vec3Track) -> Self {
+        self.translation = track;
+        self
+    }
+
+    pub fn rotation(mut self, track: RotationTrack) -> Self {
+        self.rotation = track;
+        self
+    }
+
+    pub fn scale(mut self, track: Vec3Track) -> Self {
+        self.scale = track;
+        self
+    }
+
+    /// The translation, rotation and scale at `t`.
+    pub fn parts(&self, t: f32) -> (Vec3, Quat, Vec3) {
+        let scale = match self.scale.is_empty() {
+            true => Vec3::ONE,
+            false => self.scale.sample(t),
+        };
+        (self.translation.sample(t), self.rotation.sample(t), scale)
+    }
+
+    /// The transform at `t`. Scales first, then rotates, then translates.
+    pub fn sample(&self, t: f32) -> Mat4 {
+        let (translation, rotation, scale) = self.parts(t);
+        Mat4::from_scale_rotation_translation(scale, rotation, translation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec4;
+
+    use super::*;
+    use crate::renderer::keyframe::Easing;
+
+    fn close(a: Vec4, b: Vec4) -> bool {
+        (a - b).abs().max_element() < 1e-3
+    }
+
+    #[test]
+    fn oklab_round_trips() {
+        for color in [
+            color::WHITE,
+            color::BLACK,
+            vec4(1.0, 0.0, 0.0, 0.5),
+            vec4(0.2, 0.7, 0.4, 1.0),
+            color::sinebow(0.3),
+        ] {
+            assert!(
+                close(color::from_oklab(color::to_oklab(color)), color),
+                "{color}"
+            );
+        }
+        // white is as light as it gets, with no color
+        assert!(close(
+            color::to_oklab(color::WHITE),
+            vec4(1.0, 0.0, 0.0, 1.0)
+        ));
+    }
+
+    #[test]
+    fn perceptual_colors_blend_in_oklab() {
+        let red = vec4(1.0, 0.0, 0.0, 1.0);
+        let green = vec4(0.0, 1.0, 0.0, 1.0);
+        let linear =
+            ColorTrack::new()
+                .key(0.0, red, Easing::linear())
+                .key(1.0, green, Easing::linear());
+        let perceptual = PerceptualColorTrack::new()
+            .key(0.0, red.into(), Easing::linear())
+            .key(1.0, green.into(), Easing::linear());
+        // same at the keys
+        assert!(close(perceptual.sample(0.0).0, red));
+        assert!(close(perceptual.sample(1.0).0, green));
+        // but the middle doesn't dip in lightness the way mixing the channels does
+        let lightness = |color: Color| color::to_oklab(color).x;
+        let ends = lightness(red).min(lightness(green));
+        assert!(lightness(linear.sample(0.5)) < ends);
+        assert!(lightness(perceptual.sample(0.5).0) >= ends);
+    }
+
+    #[test]
+    fn transforms_put_their_parts_together() {
+        let track = TransformTrack::new()
+            .translation(Vec3Track::new().key(0.0, Vec3::ZERO, Easing::linear()).key(
+                2.0,
+                Vec3::X * 4.0,
+                Easing::linear(),
+            ))
+            .rotation(
+                RotationTrack::new()
+                    .key(0.0, Quat::IDENTITY, Easing::linear())
+                    .key(2.0, Quat::from_rotation_z(1.0), Easing::linear()),
+            );
+        let (translation, rotation, scale) = track.parts(1.0);
+        assert!(translation.abs_diff_eq(Vec3::X * 2.0, 1e-5));
+        assert!(rotation.angle_between(Quat::from_rotation_z(0.5)) < 1e-4);
+        // unscaled without scale keys
+        assert_eq!(scale, Vec3::ONE);
+        assert!(track.sample(1.0).abs_diff_eq(
+            Mat4::from_rotation_translation(Quat::from_rotation_z(0.5), Vec3::X * 2.0),
+            1e-5
+        ));
+
+        let track = track.scale(Vec3Track::new().key(0.0, Vec3::splat(2.0), Easing::linear()));
+        let point = track.sample(2.0).transform_point3(Vec3::X);
+        assert!(point.abs_diff_eq(
+            Vec3::X * 4.0 + Quat::from_rotation_z(1.0) * Vec3::X * 2.0,
+            1e-5
+        ));
+    }
+}
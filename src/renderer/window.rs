@@ -0,0 +1,163 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::{
+    device::{Device, Queue},
+    format::NumericFormat,
+    image::{Image, ImageUsage},
+    swapchain::{
+        self, PresentMode, Surface, Swapchain, SwapchainAcquireFuture, SwapchainCreateInfo,
+        SwapchainPresentInfo,
+    },
+    sync::{future::FenceSignalFuture, GpuFuture},
+    Validated, VulkanError,
+};
+use winit::window::Window;
+
+use super::{app::CommandBuilder, ext::CommandBufferExt, target::RenderTarget};
+
+/// A window's swapchain, recreated whenever the window's size changes or the swapchain goes out of
+/// date, so nothing else has to. As a [`RenderTarget`], it shows every frame letterboxed to fit.
+pub struct WindowTarget {
+    window: Arc<Window>,
+    swapchain: Arc<Swapchain>,
+    images: Vec<Arc<Image>>,
+    /// Signalled once the last frame shown on each swapchain image has been presented.
+    presented: Vec<Option<FenceSignalFuture<Box<dyn GpuFuture>>>>,
+    recreate: bool,
+    /// The image being shown next, and its index, between [`RenderTarget::prepare`] and
+    /// [`RenderTarget::submitted`].
+    acquired: Option<(u32, Arc<Image>)>,
+}
+impl WindowTarget {
+    pub fn new(
+        device: Arc<Device>,
+        surface: Arc<Surface>,
+        window: Arc<Window>,
+        present_mode: PresentMode,
+    ) -> Result<Self> {
+        let physical_device = device.physical_device();
+        let surface_capabilities =
+            physical_device.surface_capabilities(&surface, Default::default())?;
+        let composite_alpha = surface_capabilities
+            .supported_composite_alpha
+            .into_iter()
+            .next()
+            .unwrap();
+        // frames are already encoded for display, so an srgb swapchain would encode them twice
+        let surface_formats = physical_device.surface_formats(&surface, Default::default())?;
+        let image_format = surface_formats
+            .iter()
+            .map(|&(format, _)| format)
+            .find(|format| format.numeric_format_color() != Some(NumericFormat::SRGB))
+            .unwrap_or(surface_formats[0].0);
+
+        let (swapchain, images) = Swapchain::new(
+            device.clone(),
+            surface,
+            SwapchainCreateInfo {
+                min_image_count: surface_capabilities.min_image_count + 1,
+                image_format,
+                present_mode,
+                image_extent: window.inner_size().into(),
+                image_usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_DST,
+                composite_alpha,
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self {
+            window,
+            presented: images.iter().map(|_| None).collect(),
+            swapchain,
+            images,
+            recreate: false,
+            acquired: None,
+        })
+    }
+
+    /// Acquires the next image to show a frame on, with its index and the future to wait on
+    /// before drawing to it. Returns `None` if there's nothing to show frames on right now, like
+    /// while the window is minimised or the swapchain has just gone out of date.
+    pub fn acquire(&mut self) -> Result<Option<(u32, Arc<Image>, SwapchainAcquireFuture)>> {
+        let size = self.window.inner_size();
+        if size.width == 0 || size.height == 0 {
+            return Ok(None);
+        }
+        if self.recreate || self.swapchain.image_extent() != <[u32; 2]>::from(size) {
+            let (swapchain, images) = self.swapchain.recreate(SwapchainCreateInfo {
+                image_extent: size.into(),
+                ..self.swapchain.create_info()
+            })?;
+            self.swapchain = swapchain;
+            // dropping the old fences waits for them
+            self.presented = images.iter().map(|_| None).collect();
+            self.images = images;
+            self.recreate = false;
+        }
+
+        let (index, suboptimal, acquired) =
+            match swapchain::acquire_next_image(self.swapchain.clone(), None)
+                .map_err(Validated::unwrap)
+            {
+                Ok(acquired) => acquired,
+                Err(VulkanError::OutOfDate) => {
+                    self.recreate = true;
+                    return Ok(None);
+                }
+                Err(error) => return Err(error.into()),
+            };
+        self.recreate |= suboptimal;
+
+        if let Some(presented) = self.presented[index as usize].take() {
+            presented.wait(None)?;
+        }
+        Ok(Some((index, self.images[index as usize].clone(), acquired)))
+    }
+
+    /// Presents the image at `index` once `rendered` is done.
+    pub fn present(
+        &mut self,
+        queue: &Arc<Queue>,
+        index: u32,
+        rendered: impl GpuFuture + 'static,
+    ) -> Result<()> {
+        let presented = rendered
+            .then_swapchain_present(
+                queue.clone(),
+                SwapchainPresentInfo::swapchain_image_index(self.swapchain.clone(), index),
+            )
+            .boxed()
+            .then_signal_fence_and_flush();
+        match presented.map_err(Validated::unwrap) {
+            Ok(presented) => self.presented[index as usize] = Some(presented),
+            Err(VulkanError::OutOfDate) => self.recreate = true,
+            Err(error) => return Err(error.into()),
+        }
+        Ok(())
+    }
+}
+impl RenderTarget for WindowTarget {
+    /// Frames are still drawn while there's no image to show them on.
+    fn prepare(&mut self) -> Result<Option<Box<dyn GpuFuture>>> {
+        let Some((index, image, acquired)) = self.acquire()? else {
+            return Ok(None);
+        };
+        self.acquired = Some((index, image));
+        Ok(Some(acquired.boxed()))
+    }
+
+    fn record(&mut self, builder: &mut CommandBuilder, frame: Arc<Image>) -> Result<()> {
+        if let Some((_, image)) = &self.acquired {
+            builder.blit_letterboxed(frame, image.clone())?;
+        }
+        Ok(())
+    }
+
+    fn submitted(&mut self, queue: &Arc<Queue>, rendered: Box<dyn GpuFuture>) -> Result<()> {
+        match self.acquired.take() {
+            Some((index, _)) => self.present(queue, index, rendered),
+            None => Ok(()),
+        }
+    }
+}